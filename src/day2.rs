@@ -5,24 +5,33 @@ use std::process;
 
 use crate::util;
 use crate::intcode::Computer;
+use crate::intcode::trace::TraceSettings;
 
-pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
+pub fn run(filename: &PathBuf, part2: &bool, trace: &Option<TraceSettings>) -> Result<(), Box<dyn Error>> {
     let mut initial_state = util::read_comma_separated_integers(File::open(filename)?)?;
+    let mut trace_file = trace.as_ref().map(|t| t.open()).transpose()?;
 
     if *part2 {
         let target: i32 = 19690720;
+        let mut computer = Computer::new(initial_state, None, None);
+        if let Some(file) = &trace_file {
+            computer.set_tracer(file.tracer(None));
+        }
+        let initial_snapshot = computer.snapshot();
 
         for noun in 0..100 {
             for verb in 0..100 {
-                let mut run_initial_state = initial_state.clone();
-                run_initial_state[1] = noun;
-                run_initial_state[2] = verb;
-                let mut computer = Computer::new(run_initial_state, None, None);
+                computer.restore(&initial_snapshot);
+                computer.write(1, noun)?;
+                computer.write(2, verb)?;
                 let result = computer.run();
 
                 match result {
                     Ok(x) if x == target => {
                         println!("Result: {}", 100 * noun + verb);
+                        if let Some(file) = trace_file.as_mut() {
+                            file.flush()?;
+                        }
                         process::exit(0);
                     },
                     Ok(_) => println!("Missed for noun={}, verb={}", noun, verb),
@@ -30,13 +39,23 @@ pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
                 };
             }
         }
+
+        if let Some(file) = trace_file.as_mut() {
+            file.flush()?;
+        }
     } else {
         initial_state[1] = 12;
         initial_state[2] = 2;
 
         let mut computer = Computer::new(initial_state, None, None);
+        if let Some(file) = &trace_file {
+            computer.set_tracer(file.tracer(None));
+        }
 
         let result = computer.run();
+        if let Some(file) = trace_file.as_mut() {
+            file.flush()?;
+        }
         
         match result {
             Ok(x) => println!("Result: {}", x),
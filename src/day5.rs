@@ -6,16 +6,24 @@ use std::process;
 
 use crate::util;
 use crate::intcode::{Computer, ComputerInput};
+use crate::intcode::trace::TraceSettings;
 
-pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
+pub fn run(filename: &PathBuf, part2: &bool, trace: &Option<TraceSettings>) -> Result<(), Box<dyn Error>> {
     let initial_state = util::read_comma_separated_integers(File::open(filename)?)?;
+    let mut trace_file = trace.as_ref().map(|t| t.open()).transpose()?;
 
     if *part2 {
         let mut input = VecDeque::new();
         input.push_back(5);
         let mut computer = Computer::new(initial_state, Some(ComputerInput::Queue(input)), None);
+        if let Some(file) = &trace_file {
+            computer.set_tracer(file.tracer(None));
+        }
 
         let result = computer.run();
+        if let Some(file) = trace_file.as_mut() {
+            file.flush()?;
+        }
         
         match result {
             Ok(_) => {
@@ -34,8 +42,14 @@ pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
         let mut input = VecDeque::new();
         input.push_back(1);
         let mut computer = Computer::new(initial_state, Some(ComputerInput::Queue(input)), None);
+        if let Some(file) = &trace_file {
+            computer.set_tracer(file.tracer(None));
+        }
 
         let result = computer.run();
+        if let Some(file) = trace_file.as_mut() {
+            file.flush()?;
+        }
         
         match result {
             Ok(_) => {
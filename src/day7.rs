@@ -1,138 +1,223 @@
-use std::collections::VecDeque;
 use std::error::Error;
+use std::ops::Range;
 use std::path::PathBuf;
 use std::fs::File;
-use std::{process, thread, time};
-use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
 use itertools::Itertools;
 
 use crate::util;
-use crate::intcode::{Computer, ComputerInput, ComputerOutput};
+use crate::intcode::pipeline::{Pipeline, Topology};
+use crate::intcode::iolog::IoRecorder;
+use crate::intcode::trace::{self, TraceFile, TraceSettings, Tracer};
+
+/// How to search for the best phase settings.
+pub struct Settings {
+    pub amplifiers: usize,
+    /// The phase settings to choose from, or `None` for the puzzle's range.
+    pub phases: Option<Range<i32>>,
+    /// The number of threads to search with, or `None` to choose automatically.
+    pub workers: Option<usize>,
+    /// Print every ordering's signal, not just the best.
+    pub table: bool,
+}
+
+/// The signal that reached the thrusters with one ordering of the phase settings.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Trial {
+    pub phases: Vec<i32>,
+    pub signal: i32,
+}
 
-pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
+pub fn run(filename: &PathBuf, part2: &bool, trace: &Option<TraceSettings>, record_io: &Option<PathBuf>, settings: &Settings) -> Result<(), Box<dyn Error>> {
     let initial_state = util::read_comma_separated_integers(File::open(filename)?)?;
+    let mut trace_file = trace.as_ref().map(|t| t.open()).transpose()?;
+    let recorder = record_io.as_ref().map(|_| IoRecorder::new());
+
+    let (topology, default_phases) = if *part2 { (Topology::Ring, 5..10) } else { (Topology::Chain, 0..5) };
+    let phases = settings.phases.clone().unwrap_or(default_phases);
+    // Keep the trace and I/O log in order by running one amplifier chain at a time.
+    let workers = match settings.workers {
+        Some(n) => n,
+        None if trace_file.is_some() || recorder.is_some() => 1,
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    };
+
+    let result = search(&initial_state, settings.amplifiers, phases, topology, workers, &|case, amp| {
+        instrument(&trace_file, &recorder, &format!("{}:{}", case, amp_label(amp)))
+    });
 
-    if *part2 {
-        // No action yet!
-        let possibilities = (5..10).permutations(5);
-        let mut max_result = 0;
-        let mut count = 0;
-
-        for permutation in possibilities {
-            count += 1;
-            let (tx_a, tx_a2, rx_b) = make_channel_with_spare_transmitter();
-            let (tx_b, tx_b2, rx_c) = make_channel_with_spare_transmitter();
-            let (tx_c, tx_c2, rx_d) = make_channel_with_spare_transmitter();
-            let (tx_d, tx_d2, rx_e) = make_channel_with_spare_transmitter();
-            let (tx_e, tx_e2, rx_a) = make_channel_with_spare_transmitter();
-
-            // We use the transmitter for the previous amp in the chain in each case.
-            send_phase_and_initial_value(tx_e2, permutation[0]);
-            send_phase(tx_a2, permutation[1]);
-            send_phase(tx_b2, permutation[2]);
-            send_phase(tx_c2, permutation[3]);
-            send_phase(tx_d2, permutation[4]);
-
-            // Give the messages a chance to send.
-            thread::sleep(time::Duration::from_millis(10));
-
-            launch_computer(initial_state.clone(), rx_a, tx_a);
-            launch_computer(initial_state.clone(), rx_b, tx_b);
-            launch_computer(initial_state.clone(), rx_c, tx_c);
-            launch_computer(initial_state.clone(), rx_d, tx_d);
-            let mut amp_e = make_computer(initial_state.clone(), rx_e, tx_e);
-
-            match amp_e.run() {
-                Ok(_) => {
-                    match amp_e.output().pop_front() {
-                        Some(element) => {
-                            if element > max_result {
-                                max_result = element;
-                            }
-                        },
-                        None => {
-                            eprintln!("No output found from computer for case {}: {:?}", count, permutation);
-                            process::exit(1);
-                        }
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Problem running computer: {}", e);
-                    process::exit(1);
-                }
-            }
-        }
+    // Save the trace and I/O log even if the run failed, as that's when they're most useful.
+    if let Some(file) = trace_file.as_mut() {
+        file.flush()?;
+    }
+    if let (Some(recorder), Some(path)) = (&recorder, record_io) {
+        recorder.log().save(path)?;
+    }
 
-        println!("{}", max_result);
+    let trials = result?;
+    if settings.table {
+        for trial in &trials {
+            println!("{}\t{}", trial.phases.iter().join(","), trial.signal);
+        }
+    }
+    let best = best_trial(&trials).ok_or("There are not enough phase settings for the amplifiers.")?;
+    println!("{}", best.signal);
+    println!("Phase settings: {}", best.phases.iter().join(","));
+    Ok(())
+}
 
-        Ok(())
-    } else {
-        let possibilities = (0..5).permutations(5);
-        let mut max_result = 0;
-
-        for permutation in possibilities {
-            let mut result = 0;
-            for phase in permutation {
-                let input = VecDeque::from(vec![phase, result]);
-                let mut computer = Computer::new(initial_state.clone(), Some(ComputerInput::Queue(input)), None);
-                match computer.run() {
-                    Ok(_) => {
-                        match computer.output().pop_front() {
-                            Some(element) => { result = element; },
-                            None => {
-                                eprintln!("No output found from computer!");
-                                process::exit(1);
-                            }
-                        }
+/// The numbered trials run by one search thread, or the number of the case that failed and why.
+type WorkerResult = Result<Vec<(usize, Trial)>, (usize, String)>;
+
+/// Runs the amplifiers with every ordering of `amplifiers` distinct phase settings from `phases`,
+/// spread over `workers` threads, and returns the results in the order the orderings are
+/// generated.
+///
+/// `instrument` is called with the 1-based case number and the amplifier index to make a tracer
+/// for each amplifier. Once a case fails, no further cases are started, and the failure of the
+/// earliest case is reported.
+pub fn search<F>(program: &[i32], amplifiers: usize, phases: Range<i32>, topology: Topology, workers: usize, instrument: &F) -> Result<Vec<Trial>, String>
+where
+    F: Fn(usize, usize) -> Option<Box<dyn Tracer<i32>>> + Sync,
+{
+    // The permutations restart once exhausted, so fuse them before sharing between workers.
+    let cases = Mutex::new(phases.permutations(amplifiers).fuse().enumerate());
+    // Cases are handed out in order, so every case before a failing one has already started and
+    // finishes even once the flag is set.
+    let stop = AtomicBool::new(false);
+    let results: Vec<WorkerResult> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.max(1)).map(|_| scope.spawn(|| {
+            let mut trials = vec![];
+            loop {
+                let next = if stop.load(Ordering::SeqCst) { None } else { cases.lock().unwrap().next() };
+                match next {
+                    Some((index, phases)) => {
+                        let signal = amplify(program, &phases, topology, |amp| instrument(index + 1, amp))
+                            .map_err(|e| {
+                                stop.store(true, Ordering::SeqCst);
+                                (index, format!("Case {} (phases {}): {}", index + 1, phases.iter().join(","), e))
+                            })?;
+                        trials.push((index, Trial { phases, signal }));
                     },
-                    Err(e) => {
-                        eprintln!("Problem running computer: {}", e);
-                        process::exit(1);
-                    }
+                    None => return Ok(trials)
                 }
             }
+        })).collect();
+        handles.into_iter().map(|h| h.join().expect("Search thread panicked.")).collect()
+    });
 
-            if result > max_result {
-                max_result = result;
+    let mut trials = vec![];
+    let mut failure: Option<(usize, String)> = None;
+    for result in results {
+        match result {
+            Ok(found) => trials.extend(found),
+            Err((index, message)) => if failure.as_ref().is_none_or(|(first, _)| index < *first) {
+                failure = Some((index, message));
             }
         }
-
-        println!("{}", max_result);
-
-        Ok(())
     }
+    if let Some((_, message)) = failure {
+        return Err(message);
+    }
+    trials.sort_by_key(|&(index, _)| index);
+    Ok(trials.into_iter().map(|(_, trial)| trial).collect())
 }
 
-fn make_computer(initial_state: Vec<i32>, input: Receiver<i32>, output: SyncSender<i32>) -> Computer<i32> {
-    Computer::new(initial_state, Some(ComputerInput::Channel(input)), Some(ComputerOutput::Channel(output)))
+/// Returns the trial with the highest signal, preferring the earliest if several tie.
+pub fn best_trial(trials: &[Trial]) -> Option<&Trial> {
+    trials.iter().rev().max_by_key(|t| t.signal)
 }
 
-fn launch_computer(initial_state: Vec<i32>, input: Receiver<i32>, output: SyncSender<i32>) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        let mut c = make_computer(initial_state, input, output);
-        c.run().unwrap_or_else(|e| {
-            eprintln!("Problem running computer: {}", e);
-            process::exit(1);
-        });
-    })
+/// Runs one set of amplifiers, returning the last signal sent to the thrusters.
+fn amplify<F>(program: &[i32], phases: &[i32], topology: Topology, instrument: F) -> Result<i32, String>
+where
+    F: Fn(usize) -> Option<Box<dyn Tracer<i32>>>,
+{
+    let mut pipeline = Pipeline::with_phases(program, phases, topology);
+    for (amp, computer) in pipeline.computers_mut().iter_mut().enumerate() {
+        if let Some(tracer) = instrument(amp) {
+            computer.set_tracer(tracer);
+        }
+    }
+    let outputs = pipeline.run(&[0]).map_err(|e| e.to_string())?;
+    outputs.last().cloned().ok_or_else(|| String::from("No output found from the last amplifier."))
 }
 
-fn make_channel_with_spare_transmitter() -> (SyncSender<i32>, SyncSender<i32>, Receiver<i32>) {
-    let (tx, rx) = sync_channel(0);
-    let tx2 = tx.clone();
-    (tx, tx2, rx)
+/// Names amplifiers A to Z, then by number.
+fn amp_label(amp: usize) -> String {
+    if amp < 26 {
+        ((b'A' + amp as u8) as char).to_string()
+    } else {
+        (amp + 1).to_string()
+    }
 }
 
-fn send_phase(sender: SyncSender<i32>, phase: i32) {
-    thread::spawn(move || {
-        sender.send(phase).unwrap();
-    });
+/// Makes the tracer for an amplifier, writing to the trace file and recording I/O as requested.
+fn instrument(trace_file: &Option<TraceFile>, recorder: &Option<IoRecorder<i32>>, label: &str) -> Option<Box<dyn Tracer<i32>>> {
+    let tracers: Vec<Box<dyn Tracer<i32>>> = trace_file.iter().map(|f| f.tracer(Some(label)))
+        .chain(recorder.iter().map(|r| r.tracer(label)))
+        .collect();
+    trace::combine(tracers)
 }
 
-fn send_phase_and_initial_value(sender: SyncSender<i32>, phase: i32) {
-    thread::spawn(move || {
-        sender.send(phase).unwrap();
-        sender.send(0).unwrap();
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    const CHAIN: [i32; 17] = [3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+    const RING: [i32; 29] = [3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5];
+
+    fn no_tracer(_: usize, _: usize) -> Option<Box<dyn Tracer<i32>>> {
+        None
+    }
+
+    #[test]
+    fn test_search_chain() {
+        let trials = search(&CHAIN, 5, 0..5, Topology::Chain, 3, &no_tracer).unwrap();
+        assert_eq!(120, trials.len());
+        assert_eq!(vec![0, 1, 2, 3, 4], trials[0].phases);
+        assert_eq!(Some(&Trial { phases: vec![4, 3, 2, 1, 0], signal: 43210 }), best_trial(&trials));
+    }
+
+    #[test]
+    fn test_search_ring() {
+        let trials = search(&RING, 5, 5..10, Topology::Ring, 4, &no_tracer).unwrap();
+        assert_eq!(Some(&Trial { phases: vec![9, 8, 7, 6, 5], signal: 139629729 }), best_trial(&trials));
+    }
+
+    #[test]
+    fn test_worker_count_does_not_change_results() {
+        let single = search(&CHAIN, 3, 0..6, Topology::Chain, 1, &no_tracer).unwrap();
+        assert_eq!(120, single.len());
+        assert_eq!(single, search(&CHAIN, 3, 0..6, Topology::Chain, 8, &no_tracer).unwrap());
+    }
+
+    #[test]
+    fn test_search_error() {
+        let result = search(&[3, 5, 99], 2, 0..2, Topology::Chain, 2, &no_tracer);
+        assert!(result.unwrap_err().contains("No output"));
+    }
+
+    #[test]
+    fn test_search_stops_at_first_error() {
+        let started = AtomicUsize::new(0);
+        let count = |case: usize, _: usize| {
+            started.fetch_max(case, Ordering::SeqCst);
+            None
+        };
+        let result = search(&[3, 5, 99], 3, 0..6, Topology::Chain, 4, &count);
+        assert!(result.unwrap_err().starts_with("Case 1 (phases 0,1,2): "));
+        assert!(started.load(Ordering::SeqCst) <= 8);
+    }
+
+    #[test]
+    fn test_best_trial_prefers_first() {
+        let trials = vec![Trial { phases: vec![0], signal: 5 }, Trial { phases: vec![1], signal: 5 }];
+        assert_eq!(Some(&trials[0]), best_trial(&trials));
+        assert_eq!(None, best_trial(&[]));
+    }
 }
@@ -6,8 +6,9 @@ use std::process;
 
 use crate::util;
 use crate::intcode::{Computer, ComputerInput};
+use crate::intcode::trace::TraceSettings;
 
-pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
+pub fn run(filename: &PathBuf, part2: &bool, trace: &Option<TraceSettings>) -> Result<(), Box<dyn Error>> {
     let initial_state = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
 
     let input = if *part2 {
@@ -17,7 +18,17 @@ pub fn run(filename: &PathBuf, part2: &bool) -> Result<(), Box<dyn Error>> {
     };
 
     let mut computer = Computer::new(initial_state.clone(), Some(ComputerInput::Queue(input)), None);
-    match computer.run() {
+    let mut trace_file = trace.as_ref().map(|t| t.open()).transpose()?;
+    if let Some(file) = &trace_file {
+        computer.set_tracer(file.tracer(None));
+    }
+
+    let result = computer.run();
+    if let Some(file) = trace_file.as_mut() {
+        file.flush()?;
+    }
+
+    match result {
         Ok(_) => {
             match computer.output().pop_front() {
                 Some(element) => { println!("{}", element); },
@@ -1,8 +1,39 @@
-use std::collections::{VecDeque, HashMap};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
-use std::hash::Hash;
 use std::sync::mpsc::{SyncSender, Receiver};
-use num::{Integer, Signed, FromPrimitive};
+use std::time::{Duration, Instant};
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+use itertools::Itertools;
+
+pub mod assembler;
+pub mod disassembler;
+pub mod debugger;
+pub mod snapshot;
+pub mod memory;
+pub mod error;
+pub mod trace;
+pub mod profiler;
+pub mod expression;
+pub mod breakpoints;
+pub mod history;
+pub mod iolog;
+pub mod pipeline;
+pub mod network;
+pub mod ascii;
+pub mod analysis;
+pub mod translator;
+pub mod extension;
+pub mod fuzz;
+pub mod conformance;
+mod cache;
+
+use memory::{Memory, MemoryKind};
+use error::Fault;
+pub use error::IntcodeError;
+use trace::{Operand, TraceEvent, Tracer};
+use history::Change;
+use cache::DecodeCache;
+use extension::{Activity, Opcode};
 
 pub enum ComputerOutput<T: Signed + Integer> {
     Queue(VecDeque<T>),
@@ -15,113 +46,414 @@ pub enum ComputerInput<T: Signed + Integer> {
 }
 
 pub struct Computer<T: Signed + Integer> {
-    memory: HashMap<T, T>,
+    memory: Box<dyn Memory<T>>,
     loc: T,
     running: bool,
     input: ComputerInput<T>,
     output: ComputerOutput<T>,
     alt_output: VecDeque<T>,
     relative_base: T,
+    steps: u64,
+    tracer: Option<Box<dyn Tracer<T>>>,
+    limits: Limits,
+    history: Option<Vec<Change<T>>>,
+    /// Channel input put back by `step_back`, to be read again before the channel.
+    replay: VecDeque<T>,
+    checked: bool,
+    decoded: DecodeCache<T>,
+    extensions: BTreeMap<u8, Opcode<T>>,
+    exit_code: Option<T>,
 }
 
+/// The reason that `Computer::resume` handed control back to its caller.
+#[derive(PartialEq, Eq, Debug)]
+pub enum RunStatus<T> {
+    Halted,
+    NeedsInput,
+    ProducedOutput(T)
+}
+
+/// Execution budgets for a `Computer`. A computer that exceeds one stops with an error before
+/// doing anything further, and can carry on from where it stopped once the limit is raised.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Limits {
+    /// The total number of instructions the computer may execute.
+    pub max_steps: Option<u64>,
+    /// The number of memory cells the computer may use.
+    pub max_memory: Option<usize>,
+    /// The time by which the computer must have stopped running.
+    pub deadline: Option<Instant>,
+}
+
+impl Limits {
+    /// Limits with a deadline the given time from now.
+    pub fn timeout(duration: Duration) -> Self {
+        Limits { deadline: Some(Instant::now() + duration), ..Limits::default() }
+    }
+}
+
+/// Reading the clock is slow compared to executing an instruction, so the deadline is only checked
+/// once every this many steps.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
 fn convert<T: FromPrimitive>(value: usize) -> T {
     T::from_usize(value).unwrap()
 }
 
+/// The address a number of words after another, failing if it is too large to represent.
+fn offset<T: CheckedAdd + FromPrimitive>(address: &T, words: usize) -> Result<T, Fault<T>> {
+    address.checked_add(&convert(words)).ok_or(Fault::Overflow)
+}
+
+/// Converts a value to a memory address, failing if it is negative.
+fn to_address<T: ToPrimitive + Clone>(value: &T) -> Result<usize, Fault<T>> {
+    value.to_usize().ok_or_else(|| Fault::NegativeAddress(value.clone()))
+}
+
 impl<T: Signed + Integer + fmt::Debug> fmt::Debug for Computer<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Memory: {:?} Location: {:?} Running: {:?}", self.memory, self.loc, self.running)
+        write!(f, "Memory: {:?} Location: {:?} Running: {:?}", self.memory.cells(), self.loc, self.running)
     }
 }
 
-impl<T> Computer<T> where T: Integer + Signed + Copy + FromPrimitive + Hash + fmt::Display {
+impl<T> Computer<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
     /// Creates a new Computer.
     ///
     /// The function takes the intial memory state for the computer, plus an optional input and
     /// output. An empty queue is used as the default for input and output, if no alternative is
-    /// supplied.
+    /// supplied. The program is held in dense memory; use `with_memory` to choose another kind.
     pub fn new(memory: Vec<T>, input: Option<ComputerInput<T>>, output: Option<ComputerOutput<T>>) -> Self {
-        let mem_map = (0..).zip(memory).map(|(k,v)| (convert(k), v.clone())).collect();
+        Computer::with_memory(MemoryKind::Dense.load(memory), input, output)
+    }
+
+    /// Creates a new Computer using the given memory, which should already hold the program.
+    pub fn with_memory(memory: Box<dyn Memory<T>>, input: Option<ComputerInput<T>>, output: Option<ComputerOutput<T>>) -> Self {
         Computer { 
-            memory: mem_map, 
+            memory, 
             loc: convert(0), 
             running: true, 
             input: input.unwrap_or(ComputerInput::Queue(VecDeque::new())),
             output: output.unwrap_or(ComputerOutput::Queue(VecDeque::new())),
             alt_output: VecDeque::new(),
-            relative_base: convert(0)
+            relative_base: convert(0),
+            steps: 0,
+            tracer: None,
+            limits: Limits::default(),
+            history: None,
+            replay: VecDeque::new(),
+            checked: false,
+            decoded: DecodeCache::new(),
+            extensions: BTreeMap::new(),
+            exit_code: None
         }
     }
 
-    pub fn run(&mut self) -> Result<T, String> {
+    pub fn run(&mut self) -> Result<T, IntcodeError<T>> {
         while self.running {
             self.step()?;
         }
 
-        self.result()
+        Ok(self.memory.read(0))
     }
 
-    fn step(&mut self) -> Result<(), String> {
-        let current_mem_value = self.memory.get(&self.loc);
-        let (instruction_code, argument_types) = match current_mem_value {
-            Some(x) => match Computer::read_instruction_code(*x) {
-                Ok((a, b)) => (a, b),
-                Err(err) => return Err(err)
-            },
-            None => return Err(format!("Current location {} is out of range.", self.loc))
-        };
-
-        Instruction::new(instruction_code, self.loc, argument_types, &self.memory, self.relative_base)
-            .and_then(|i| i.call(&mut self.memory, &mut self.input, &mut self.output, &mut self.alt_output, &mut self.relative_base))
-            .and_then(|result| match result {
-                CallResult::Step(distance) => {
-                    self.loc = self.loc + distance;
-                    Ok(())
+    /// Runs the computer until it halts, needs input that isn't available, or produces an output.
+    ///
+    /// Unlike `run`, an empty input queue is not an error: the computer stops before the input
+    /// instruction and reports `NeedsInput`, and can be resumed once `push_input` has been called.
+    /// An output value is removed from the output queue and returned to the caller. Computers
+    /// reading from a channel still block waiting for input.
+    pub fn resume(&mut self) -> Result<RunStatus<T>, IntcodeError<T>> {
+        while self.running {
+            let instruction = self.fetch()?;
+            match &instruction {
+                Instruction::Input(_) => match &self.input {
+                    ComputerInput::Queue(q) if q.is_empty() && self.replay.is_empty() => return Ok(RunStatus::NeedsInput),
+                    _ => self.execute(instruction)?
                 },
-                CallResult::Jump(target) => {
-                    self.loc = target;
-                    Ok(())
+                Instruction::Output(source) => {
+                    let value = source.get(self.memory.as_ref()).map_err(|f| self.fault(f))?;
+                    self.execute(instruction)?;
+                    if let ComputerOutput::Queue(q) = &mut self.output {
+                        q.pop_back();
+                        self.output_taken();
+                    }
+                    return Ok(RunStatus::ProducedOutput(value));
                 },
-                CallResult::Stop => {
-                    self.running = false;
-                    Ok(())
-                }
-            })
+                _ => self.execute(instruction)?
+            }
+        }
+
+        Ok(RunStatus::Halted)
+    }
 
+    /// Executes a single instruction at the current location.
+    pub fn step(&mut self) -> Result<(), IntcodeError<T>> {
+        self.step_with_activity().map(|_| ())
     }
 
-    fn read_instruction_code(code: T) -> Result<(T, Vec<ArgumentKind>), String> {
-        let min_opcode = convert(1);
-        let max_opcode = convert(99);
-        let divisor = convert(100);
+    /// Executes a single instruction at the current location, returning what its handler did if
+    /// it was an extension instruction.
+    fn step_with_activity(&mut self) -> Result<Option<Activity<T>>, IntcodeError<T>> {
+        let instruction = self.fetch()?;
+        self.execute_with_activity(instruction)
+    }
 
-        if code < min_opcode {
-            return Err(format!("Opcode must be positive, but got {}", code));
+    /// Decodes the instruction at the current location, reusing the decoding from an earlier
+    /// visit if the code hasn't been written since.
+    fn fetch(&mut self) -> Result<Instruction<T>, IntcodeError<T>> {
+        let address = to_address(&self.loc).map_err(|f| self.fault(f))?;
+        if let Some(instruction) = self.decoded.get(address) {
+            return Ok(instruction.with_relative_base(&self.relative_base));
         }
+        let instruction = self.current_instruction()?;
+        self.decoded.insert(address, instruction.clone(), instruction.arguments().len() + 1);
+        Ok(instruction)
+    }
+
+    fn execute(&mut self, instruction: Instruction<T>) -> Result<(), IntcodeError<T>> {
+        self.execute_with_activity(instruction).map(|_| ())
+    }
 
-        let abs_code = code.abs();
-        if abs_code <= max_opcode {
-            return Ok((code, vec![]));
+    fn execute_with_activity(&mut self, instruction: Instruction<T>) -> Result<Option<Activity<T>>, IntcodeError<T>> {
+        self.check_limits()?;
+        // An instruction running up to the largest address has nowhere to continue from.
+        if !matches!(instruction, Instruction::Stop) {
+            offset(&self.loc, instruction.arguments().len() + 1).map_err(|f| self.fault(f))?;
+        }
+        let origin = self.limits.max_memory.map(|_| (self.loc.clone(), self.read_raw(&self.loc)));
+        let traced = match self.tracer {
+            Some(_) => self.resolve_operands(&instruction).ok().map(|operands| (self.read_raw(&self.loc), operands)),
+            None => None
+        };
+        let change = self.begin_change(&instruction);
+        let mut activity = None;
+        let result = if let Instruction::Extension { opcode, arguments, .. } = &instruction {
+            let (result, done) = self.call_extension(*opcode, arguments)?;
+            activity = Some(done);
+            result
+        } else if self.replay.is_empty() {
+            instruction.call(self.memory.as_mut(), &mut self.input, &mut self.output, &mut self.alt_output, &mut self.relative_base, self.checked)
+                .map_err(|f| self.fault(f))?
+        } else {
+            let mut replay = ComputerInput::Queue(std::mem::take(&mut self.replay));
+            let result = instruction.call(self.memory.as_mut(), &mut replay, &mut self.output, &mut self.alt_output, &mut self.relative_base, self.checked);
+            if let ComputerInput::Queue(q) = replay {
+                self.replay = q;
+            }
+            result.map_err(|f| self.fault(f))?
+        };
+        if let Some(address) = instruction.written_argument().and_then(|a| a.address()).and_then(|a| a.to_usize()) {
+            self.decoded.invalidate(address);
+        }
+        if let Some((raw, operands)) = traced {
+            self.trace(&instruction, raw, operands, activity.as_ref());
+        }
+        if let Some(change) = change {
+            self.end_change(&instruction, change, activity.as_ref());
         }
 
-        let prefix = (abs_code / divisor).to_string();
-        if !prefix.chars().all(|x| x == '0' || x == '1' || x == '2') {
-            return Err(format!("Unrecognised opcode format: {}", code));
+        self.steps += 1;
+        match result {
+            CallResult::Step(distance) => self.loc = self.loc.clone() + distance,
+            CallResult::Jump(target) => self.loc = target,
+            CallResult::Stop => self.running = false
+        };
+
+        if let (Some(limit), Some((address, raw))) = (self.limits.max_memory, origin) {
+            if self.memory.footprint() > limit {
+                return Err(Fault::MemoryLimitExceeded(limit).at(address, raw));
+            }
         }
-        
-        Ok((code % divisor, (code.abs() / divisor).to_string().chars().rfold(vec![], |mut acc, x| match x {
-            '0' => { acc.push(ArgumentKind::Position); acc },
-            '1' => { acc.push(ArgumentKind::Immediate); acc },
-            _ => { acc.push(ArgumentKind::Relative); acc }
-        })))
+        Ok(activity)
     }
 
-    fn result(&self) -> Result<T, String> {
-        let target = convert(0);
-        match self.memory.get(&target) {
-            Some(a) => Ok(a.clone()),
-            _ => Err(String::from("Empty memory!"))
+    /// Checks the step budget and deadline before an instruction is executed.
+    fn check_limits(&self) -> Result<(), IntcodeError<T>> {
+        if let Some(limit) = self.limits.max_steps {
+            if self.steps >= limit {
+                return Err(self.fault(Fault::StepLimitExceeded(limit)));
+            }
         }
+        if let Some(deadline) = self.limits.deadline {
+            if self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(self.fault(Fault::DeadlineExceeded));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the execution budgets for the computer. Steps already executed count towards the new
+    /// step limit.
+    ///
+    /// The memory limit is checked after each instruction: an instruction that grows memory past
+    /// the limit completes, and the error reports its address.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Turns overflow checks on or off. With checks on, an addition, multiplication or relative
+    /// base adjustment whose result doesn't fit in `T` fails with `IntcodeError::Overflow` rather
+    /// than wrapping or panicking. Values of an arbitrary-precision type never overflow.
+    pub fn set_overflow_checks(&mut self, checked: bool) {
+        self.checked = checked;
+    }
+
+    pub fn overflow_checks(&self) -> bool {
+        self.checked
+    }
+
+    fn resolve_operands(&self, instruction: &Instruction<T>) -> Result<Vec<Operand<T>>, Fault<T>> {
+        instruction.arguments().iter()
+            .map(|a| Ok(Operand { kind: a.kind.clone(), raw: a.value.clone(), address: a.address(), value: a.get(self.memory.as_ref())? }))
+            .collect()
+    }
+
+    /// Reports an instruction that has just been executed to the tracer, along with what the
+    /// handler did if it was an extension instruction. This must be called before the location
+    /// is updated.
+    fn trace(&mut self, instruction: &Instruction<T>, raw: T, operands: Vec<Operand<T>>, activity: Option<&Activity<T>>) {
+        let writes: Vec<(T, T)> = match activity {
+            Some(activity) => activity.writes.iter().map(|(a, _, new)| (a.clone(), new.clone())).collect(),
+            None => instruction.written_argument()
+                .and_then(|a| a.address())
+                .and_then(|a| to_address(&a).ok().map(|index| (a, self.memory.read(index))))
+                .into_iter()
+                .collect()
+        };
+        let event = TraceEvent {
+            step: self.steps,
+            address: self.loc.clone(),
+            instruction: raw,
+            text: instruction.to_string(),
+            inputs: match (instruction, activity) {
+                (_, Some(activity)) => activity.inputs.clone(),
+                (Instruction::Input(_), None) => writes.iter().map(|(_, v)| v.clone()).collect(),
+                _ => vec![]
+            },
+            outputs: match (instruction, activity) {
+                (_, Some(activity)) => activity.outputs.clone(),
+                (Instruction::Output(_), None) => operands.iter().map(|o| o.value.clone()).collect(),
+                _ => vec![]
+            },
+            operands,
+            writes,
+        };
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(&event);
+        }
+    }
+
+    /// Sets a tracer to be told about every instruction the computer executes.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer<T>>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Removes the tracer, returning it so that it can be flushed.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer<T>>> {
+        self.tracer.take()
+    }
+
+    /// Returns the number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Decodes the instruction at the current location, without executing it.
+    pub fn current_instruction(&self) -> Result<Instruction<T>, IntcodeError<T>> {
+        let current_mem_value = self.memory.read(to_address(&self.loc).map_err(|f| self.fault(f))?);
+        Computer::read_instruction_code(current_mem_value)
+            .and_then(|(code, argument_types)| match code.to_u8().and_then(|c| self.extensions.get(&c).map(|o| (c, o))) {
+                Some((c, opcode)) => opcode.decode(c, self.loc.clone(), argument_types, self.memory.as_ref(), self.relative_base.clone()),
+                None => Instruction::new(code, self.loc.clone(), argument_types, self.memory.as_ref(), self.relative_base.clone())
+            })
+            .map_err(|f| self.fault(f))
+    }
+
+    /// Attaches the current location and instruction to a fault.
+    fn fault(&self, fault: Fault<T>) -> IntcodeError<T> {
+        fault.at(self.loc.clone(), self.read_raw(&self.loc))
+    }
+
+    /// Reads memory for diagnostic purposes, treating a negative address as holding zero.
+    fn read_raw(&self, address: &T) -> T {
+        to_address(address).map(|a| self.memory.read(a)).unwrap_or_else(|_| convert(0))
+    }
+
+    pub fn loc(&self) -> T {
+        self.loc.clone()
+    }
+
+    pub fn set_loc(&mut self, loc: T) {
+        self.loc = loc;
+    }
+
+    pub fn relative_base(&self) -> T {
+        self.relative_base.clone()
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: T) {
+        self.relative_base = relative_base;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Reads the value at the given address. Memory that has never been written reads as zero.
+    pub fn read(&self, address: T) -> Result<T, IntcodeError<T>> {
+        Ok(self.memory.read(to_address(&address).map_err(|f| self.fault(f))?))
+    }
+
+    pub fn write(&mut self, address: T, value: T) -> Result<(), IntcodeError<T>> {
+        let address = to_address(&address).map_err(|f| self.fault(f))?;
+        self.memory.write(address, value);
+        self.decoded.invalidate(address);
+        Ok(())
+    }
+
+    /// Returns the number of memory cells currently in use.
+    pub fn memory_footprint(&self) -> usize {
+        self.memory.footprint()
+    }
+
+    /// Adds a value to the end of the input queue. Computers reading from a channel can't be fed
+    /// this way.
+    pub fn push_input(&mut self, value: T) -> Result<(), String> {
+        match &mut self.input {
+            ComputerInput::Queue(q) => {
+                q.push_back(value);
+                Ok(())
+            },
+            ComputerInput::Channel(_) => Err(String::from("Can't push input to a computer reading from a channel."))
+        }
+    }
+
+    /// Splits an instruction into its opcode and the modes of its parameters, first parameter
+    /// first. Parameters beyond the last mode digit given are in position mode.
+    fn read_instruction_code(code: T) -> Result<(T, Vec<ArgumentKind>), Fault<T>> {
+        if code < convert(1) {
+            return Err(Fault::UnknownOpcode);
+        }
+
+        let (mut modes, opcode) = code.div_rem(&convert(100));
+        let ten: T = convert(10);
+        let mut argument_types = vec![];
+        while !modes.is_zero() {
+            let (rest, digit) = modes.div_rem(&ten);
+            argument_types.push(match digit.to_u8() {
+                Some(0) => ArgumentKind::Position,
+                Some(1) => ArgumentKind::Immediate,
+                Some(2) => ArgumentKind::Relative,
+                _ => return Err(Fault::BadParameterMode)
+            });
+            modes = rest;
+        }
+
+        Ok((opcode, argument_types))
     }
 
     pub fn output(&self) -> VecDeque<T> {
@@ -133,49 +465,80 @@ impl<T> Computer<T> where T: Integer + Signed + Copy + FromPrimitive + Hash + fm
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
-enum ArgumentKind {
+pub enum ArgumentKind {
     Position,
     Immediate,
     Relative
 }
 
-#[derive(PartialEq, Eq, Debug)]
-struct Argument<T> {
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Argument<T> {
     value: T,
     kind: ArgumentKind,
     relative_base: T
 }
 
-impl<T> Argument<T> where T: Integer + Signed + Copy + Hash {
+impl<T> Argument<T> where T: Integer + Signed + Clone + CheckedAdd + ToPrimitive + fmt::Display {
     fn new(value: T, kind: Option<&ArgumentKind>, relative_base: T) -> Self {
         Argument { value: value, kind: kind.cloned().unwrap_or(ArgumentKind::Position), relative_base: relative_base }
     }
 
-    fn get<'a>(&self, memory: &'a HashMap<T, T>) -> Option<T> {
+    /// The raw parameter value, as stored in the program.
+    pub fn value(&self) -> T {
+        self.value.clone()
+    }
+
+    pub fn kind(&self) -> &ArgumentKind {
+        &self.kind
+    }
+
+    /// The memory address the argument refers to, or `None` for an immediate argument or a
+    /// relative one whose address is too large to represent.
+    pub fn address(&self) -> Option<T> {
+        self.target().ok().flatten()
+    }
+
+    /// The memory address the argument refers to, or `None` for an immediate argument, failing
+    /// if adding the relative base overflows.
+    fn target(&self) -> Result<Option<T>, Fault<T>> {
         match self.kind {
-            ArgumentKind::Immediate => Some(self.value.clone()),
-            ArgumentKind::Position => memory.get(&self.value).cloned(),
-            ArgumentKind::Relative => memory.get(&(self.value + self.relative_base)).cloned()
+            ArgumentKind::Immediate => Ok(None),
+            ArgumentKind::Position => Ok(Some(self.value.clone())),
+            ArgumentKind::Relative => self.value.checked_add(&self.relative_base).map(Some).ok_or(Fault::Overflow)
         }
     }
 
-    fn set(&self, memory: &mut HashMap<T,T>, new_value: T) -> Result<(), String> {
-        match self.kind {
-            ArgumentKind::Immediate => Err(String::from("Can't populate Immediate argument.")),
-            ArgumentKind::Position => {
-                memory.insert(self.value, new_value);
-                Ok(())
-            },
-            ArgumentKind::Relative => {
-                memory.insert(self.value + self.relative_base, new_value);
+    fn get(&self, memory: &dyn Memory<T>) -> Result<T, Fault<T>> {
+        match self.target()? {
+            None => Ok(self.value.clone()),
+            Some(address) => Ok(memory.read(to_address(&address)?))
+        }
+    }
+
+    fn set(&self, memory: &mut dyn Memory<T>, new_value: T) -> Result<(), Fault<T>> {
+        match self.target()? {
+            None => Err(Fault::WriteToImmediate),
+            Some(address) => {
+                memory.write(to_address(&address)?, new_value);
                 Ok(())
             }
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
-enum Instruction<T> {
+impl<T: Integer + Signed + fmt::Display> fmt::Display for Argument<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ArgumentKind::Position => write!(f, "[{}]", self.value),
+            ArgumentKind::Immediate => write!(f, "#{}", self.value),
+            ArgumentKind::Relative if self.value.is_negative() => write!(f, "rb{}", self.value),
+            ArgumentKind::Relative => write!(f, "rb+{}", self.value)
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Instruction<T> {
     Add(Argument<T>, Argument<T>, Argument<T>),
     Multiply(Argument<T>, Argument<T>, Argument<T>),
     Input(Argument<T>),
@@ -185,7 +548,43 @@ enum Instruction<T> {
     LessThan(Argument<T>, Argument<T>, Argument<T>),
     Equals(Argument<T>, Argument<T>, Argument<T>),
     AdjustRelativeBase(Argument<T>),
-    Stop
+    Stop,
+    /// An instruction using an opcode registered with `Computer::register_opcode`.
+    Extension { opcode: u8, mnemonic: &'static str, arguments: Vec<Argument<T>> }
+}
+
+impl<T> Instruction<T> {
+    /// The assembler mnemonic for the instruction.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add(_, _, _) => "add",
+            Instruction::Multiply(_, _, _) => "mul",
+            Instruction::Input(_) => "in",
+            Instruction::Output(_) => "out",
+            Instruction::JumpIfTrue(_, _) => "jt",
+            Instruction::JumpIfFalse(_, _) => "jf",
+            Instruction::LessThan(_, _, _) => "lt",
+            Instruction::Equals(_, _, _) => "eq",
+            Instruction::AdjustRelativeBase(_) => "arb",
+            Instruction::Stop => "hlt",
+            Instruction::Extension { mnemonic, .. } => mnemonic
+        }
+    }
+}
+
+impl<T: Integer + Signed + fmt::Display> fmt::Display for Instruction<T> {
+    /// Formats the instruction using the same syntax as the assembler.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Add(a, b, c) | Instruction::Multiply(a, b, c) | Instruction::LessThan(a, b, c) | Instruction::Equals(a, b, c) =>
+                write!(f, "{} {}, {}, {}", self.mnemonic(), a, b, c),
+            Instruction::JumpIfTrue(a, b) | Instruction::JumpIfFalse(a, b) => write!(f, "{} {}, {}", self.mnemonic(), a, b),
+            Instruction::Input(a) | Instruction::Output(a) | Instruction::AdjustRelativeBase(a) => write!(f, "{} {}", self.mnemonic(), a),
+            Instruction::Stop => write!(f, "{}", self.mnemonic()),
+            Instruction::Extension { arguments, .. } if arguments.is_empty() => write!(f, "{}", self.mnemonic()),
+            Instruction::Extension { arguments, .. } => write!(f, "{} {}", self.mnemonic(), arguments.iter().join(", "))
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -195,9 +594,13 @@ enum CallResult<T> {
     Stop
 }
 
-impl<T> Instruction<T> where T: Integer + Signed + Copy + FromPrimitive + Hash + fmt::Display {
-    fn new(code: T, base_location: T, argument_types: Vec<ArgumentKind>, memory: &HashMap<T,T>, relative_base: T) -> Result<Self, String> {
-        let address = |x: T| *(memory.get(&x).unwrap());
+impl<T> Instruction<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display {
+    fn new(code: T, base_location: T, argument_types: Vec<ArgumentKind>, memory: &dyn Memory<T>, relative_base: T) -> Result<Self, Fault<T>> {
+        // Reads the nth parameter following the opcode.
+        let argument = |n: usize| -> Result<Argument<T>, Fault<T>> {
+            let value = memory.read(to_address(&offset(&base_location, n)?)?);
+            Ok(Argument::new(value, argument_types.get(n - 1), relative_base.clone()))
+        };
         let add: T = convert(1);
         let multiply: T = convert(2);
         let input: T = convert(3);
@@ -210,44 +613,99 @@ impl<T> Instruction<T> where T: Integer + Signed + Copy + FromPrimitive + Hash +
         let stop: T = convert(99);
         match code {
             a if a == add => {
-                Ok(Instruction::Add(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base),
-                                    Argument::new(address(base_location + convert(2)), argument_types.get(1), relative_base),
-                                    Argument::new(address(base_location + convert(3)), argument_types.get(2), relative_base)))
+                Ok(Instruction::Add(argument(1)?, argument(2)?, argument(3)?))
             },
             a if a == multiply => {
-                Ok(Instruction::Multiply(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base),
-                                         Argument::new(address(base_location + convert(2)), argument_types.get(1), relative_base),
-                                         Argument::new(address(base_location + convert(3)), argument_types.get(2), relative_base)))
+                Ok(Instruction::Multiply(argument(1)?, argument(2)?, argument(3)?))
             },
             a if a == input => {
-                Ok(Instruction::Input(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base)))
+                Ok(Instruction::Input(argument(1)?))
             },
             a if a == output => {
-                Ok(Instruction::Output(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base)))
+                Ok(Instruction::Output(argument(1)?))
             },
             a if a == jump_if_true => {
-                Ok(Instruction::JumpIfTrue(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base),
-                                           Argument::new(address(base_location + convert(2)), argument_types.get(1), relative_base)))
+                Ok(Instruction::JumpIfTrue(argument(1)?, argument(2)?))
             },
             a if a == jump_if_false => {
-                Ok(Instruction::JumpIfFalse(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base),
-                                            Argument::new(address(base_location + convert(2)), argument_types.get(1), relative_base)))
+                Ok(Instruction::JumpIfFalse(argument(1)?, argument(2)?))
             },
             a if a == less_than => {
-                Ok(Instruction::LessThan(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base),
-                                         Argument::new(address(base_location + convert(2)), argument_types.get(1), relative_base),
-                                         Argument::new(address(base_location + convert(3)), argument_types.get(2), relative_base)))
+                Ok(Instruction::LessThan(argument(1)?, argument(2)?, argument(3)?))
             },
             a if a == equals => {
-                Ok(Instruction::Equals(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base),
-                                       Argument::new(address(base_location + convert(2)), argument_types.get(1), relative_base),
-                                       Argument::new(address(base_location + convert(3)), argument_types.get(2), relative_base)))
+                Ok(Instruction::Equals(argument(1)?, argument(2)?, argument(3)?))
             },
             a if a == adjust_relative_base => {
-                Ok(Instruction::AdjustRelativeBase(Argument::new(address(base_location + convert(1)), argument_types.get(0), relative_base)))
+                Ok(Instruction::AdjustRelativeBase(argument(1)?))
             },
             a if a == stop => Ok(Instruction::Stop),
-            x => Err(format!("Unsupported instruction: {}", x))
+            _ => Err(Fault::UnknownOpcode)
+        }
+    }
+
+    /// Returns the number of parameters taken by the instruction with the given opcode, or `None`
+    /// if the opcode is not recognised.
+    fn parameter_count(code: T) -> Option<usize> {
+        let is = |opcodes: &[usize]| opcodes.iter().any(|&x| code == convert(x));
+        if is(&[1, 2, 7, 8]) {
+            Some(3)
+        } else if is(&[5, 6]) {
+            Some(2)
+        } else if is(&[3, 4, 9]) {
+            Some(1)
+        } else if is(&[99]) {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    pub fn arguments(&self) -> Vec<&Argument<T>> {
+        match self {
+            Instruction::Add(a, b, c) => vec![a, b, c],
+            Instruction::Multiply(a, b, c) => vec![a, b, c],
+            Instruction::Input(a) => vec![a],
+            Instruction::Output(a) => vec![a],
+            Instruction::JumpIfTrue(a, b) => vec![a, b],
+            Instruction::JumpIfFalse(a, b) => vec![a, b],
+            Instruction::LessThan(a, b, c) => vec![a, b, c],
+            Instruction::Equals(a, b, c) => vec![a, b, c],
+            Instruction::AdjustRelativeBase(a) => vec![a],
+            Instruction::Stop => vec![],
+            Instruction::Extension { arguments, .. } => arguments.iter().collect()
+        }
+    }
+
+    /// Returns a copy of the instruction with relative-mode arguments resolved against a
+    /// different relative base.
+    fn with_relative_base(&self, relative_base: &T) -> Self {
+        let rebase = |a: &Argument<T>| Argument::new(a.value.clone(), Some(&a.kind), relative_base.clone());
+        match self {
+            Instruction::Add(a, b, c) => Instruction::Add(rebase(a), rebase(b), rebase(c)),
+            Instruction::Multiply(a, b, c) => Instruction::Multiply(rebase(a), rebase(b), rebase(c)),
+            Instruction::Input(a) => Instruction::Input(rebase(a)),
+            Instruction::Output(a) => Instruction::Output(rebase(a)),
+            Instruction::JumpIfTrue(a, b) => Instruction::JumpIfTrue(rebase(a), rebase(b)),
+            Instruction::JumpIfFalse(a, b) => Instruction::JumpIfFalse(rebase(a), rebase(b)),
+            Instruction::LessThan(a, b, c) => Instruction::LessThan(rebase(a), rebase(b), rebase(c)),
+            Instruction::Equals(a, b, c) => Instruction::Equals(rebase(a), rebase(b), rebase(c)),
+            Instruction::AdjustRelativeBase(a) => Instruction::AdjustRelativeBase(rebase(a)),
+            Instruction::Stop => Instruction::Stop,
+            Instruction::Extension { opcode, mnemonic, arguments } =>
+                Instruction::Extension { opcode: *opcode, mnemonic, arguments: arguments.iter().map(rebase).collect() }
+        }
+    }
+
+    /// Returns the argument that the instruction writes its result to, if any.
+    pub fn written_argument(&self) -> Option<&Argument<T>> {
+        match self {
+            Instruction::Add(_, _, output) => Some(output),
+            Instruction::Multiply(_, _, output) => Some(output),
+            Instruction::Input(destination) => Some(destination),
+            Instruction::LessThan(_, _, output) => Some(output),
+            Instruction::Equals(_, _, output) => Some(output),
+            _ => None
         }
     }
 
@@ -262,66 +720,71 @@ impl<T> Instruction<T> where T: Integer + Signed + Copy + FromPrimitive + Hash +
             Instruction::LessThan(_,_,_) => convert(4),
             Instruction::Equals(_,_,_) => convert(4),
             Instruction::AdjustRelativeBase(_) => convert(2),
-            Instruction::Stop => convert(0)
+            Instruction::Stop => convert(0),
+            Instruction::Extension { arguments, .. } => convert(arguments.len() + 1)
         }
     }
 
     fn call<'a>(&self, 
-                memory: &mut HashMap<T,T>, 
+                memory: &mut dyn Memory<T>, 
                 reader: &mut ComputerInput<T>, 
                 writer: &mut ComputerOutput<T>, 
                 alt_output: &mut VecDeque<T>,
-                relative_base: &mut T) -> Result<CallResult<T>, String> {
+                relative_base: &mut T,
+                checked: bool) -> Result<CallResult<T>, Fault<T>> {
         match self {
-            Instruction::Add(input1, input2, output) => self.add(input1, input2, output, memory),
-            Instruction::Multiply(input1, input2, output) => self.multiply(input1, input2, output, memory),
+            Instruction::Add(input1, input2, output) => self.add(input1, input2, output, memory, checked),
+            Instruction::Multiply(input1, input2, output) => self.multiply(input1, input2, output, memory, checked),
             Instruction::Input(destination) => self.input(destination, memory, reader),
             Instruction::Output(source) => self.output(source, memory, writer, alt_output),
             Instruction::JumpIfTrue(input, target) => self.jump_if_true(input, target, memory),
             Instruction::JumpIfFalse(input, target) => self.jump_if_false(input, target, memory),
             Instruction::LessThan(input1, input2, output) => self.less_than(input1, input2, output, memory),
             Instruction::Equals(input1, input2, output) => self.equals(input1, input2, output, memory),
-            Instruction::AdjustRelativeBase(input) => self.adjust_relative_base(input, memory, relative_base),
+            Instruction::AdjustRelativeBase(input) => self.adjust_relative_base(input, memory, relative_base, checked),
             Instruction::Stop => Ok(CallResult::Stop),
+            Instruction::Extension { .. } => unreachable!("Extension instructions are run by the computer.")
         }
     }
 
-    fn add(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut HashMap<T,T>) -> Result<CallResult<T>, String> {
-        let result = input1.get(memory).unwrap_or_else(|| convert(0)) + input2.get(memory).unwrap_or_else(|| convert(0));
+    fn add(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut dyn Memory<T>, checked: bool) -> Result<CallResult<T>, Fault<T>> {
+        let (value1, value2) = (input1.get(memory)?, input2.get(memory)?);
+        let result = if checked { value1.checked_add(&value2).ok_or(Fault::Overflow)? } else { value1 + value2 };
         output.set(memory, result).and(Ok(CallResult::Step(self.length())))
     }
 
-    fn multiply(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut HashMap<T,T>) -> Result<CallResult<T>, String> {
-        let result = input1.get(memory).unwrap_or_else(|| convert(0)) * input2.get(memory).unwrap_or_else(|| convert(0));
+    fn multiply(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut dyn Memory<T>, checked: bool) -> Result<CallResult<T>, Fault<T>> {
+        let (value1, value2) = (input1.get(memory)?, input2.get(memory)?);
+        let result = if checked { value1.checked_mul(&value2).ok_or(Fault::Overflow)? } else { value1 * value2 };
         output.set(memory, result).and(Ok(CallResult::Step(self.length())))
     }
 
-    fn input<'a>(&self, destination: &Argument<T>, memory: &mut HashMap<T,T>, input: &mut ComputerInput<T>) -> Result<CallResult<T>, String> {
+    fn input<'a>(&self, destination: &Argument<T>, memory: &mut dyn Memory<T>, input: &mut ComputerInput<T>) -> Result<CallResult<T>, Fault<T>> {
         match input {
             ComputerInput::Queue(q) => match q.pop_front() {
                 Some(value) => {
                     destination.set(memory, value)?;
                     Ok(CallResult::Step(self.length()))
                 },
-                None => Err(String::from("Failed to find an input value."))
+                None => Err(Fault::InputExhausted)
             },
             ComputerInput::Channel(rx) => match rx.recv() {
                 Ok(val) => {
                     destination.set(memory, val)?;
                     Ok(CallResult::Step(self.length()))
                 },
-                Err(_) => Err(String::from("Failed to receive an input value."))
+                Err(_) => Err(Fault::ChannelClosed)
             }
         }
     }
 
-    fn output<'a>(&self, source: &Argument<T>, memory: &mut HashMap<T,T>, output: &mut ComputerOutput<T>, alt_output: &mut VecDeque<T>) -> Result<CallResult<T>, String> {
-        let value = source.get(memory).unwrap_or_else(|| convert(0));
+    fn output<'a>(&self, source: &Argument<T>, memory: &mut dyn Memory<T>, output: &mut ComputerOutput<T>, alt_output: &mut VecDeque<T>) -> Result<CallResult<T>, Fault<T>> {
+        let value = source.get(memory)?;
         match output {
             ComputerOutput::Queue(q) => q.push_back(value),
             ComputerOutput::Channel(tx) => match tx.send(value) {
-                Err(_) => {
-                    alt_output.push_back(value);
+                Err(e) => {
+                    alt_output.push_back(e.0);
                 },
                 _ => ()
             }
@@ -329,38 +792,39 @@ impl<T> Instruction<T> where T: Integer + Signed + Copy + FromPrimitive + Hash +
         Ok(CallResult::Step(self.length()))
     }
 
-    fn jump_if_true(&self, input: &Argument<T>, target: &Argument<T>, memory: &mut HashMap<T,T>) -> Result<CallResult<T>, String> {
-        let test_val = input.get(memory).unwrap_or_else(|| convert(0));
+    fn jump_if_true(&self, input: &Argument<T>, target: &Argument<T>, memory: &mut dyn Memory<T>) -> Result<CallResult<T>, Fault<T>> {
+        let test_val = input.get(memory)?;
         if test_val == convert(0) {
             Ok(CallResult::Step(self.length()))
         } else {
-            Ok(CallResult::Jump(target.get(memory).unwrap_or_else(|| convert(0))))
+            Ok(CallResult::Jump(target.get(memory)?))
         }
     }
 
-    fn jump_if_false(&self, input: &Argument<T>, target: &Argument<T>, memory: &mut HashMap<T,T>) -> Result<CallResult<T>, String> {
-        let test_val = input.get(memory).unwrap_or_else(|| convert(0));
+    fn jump_if_false(&self, input: &Argument<T>, target: &Argument<T>, memory: &mut dyn Memory<T>) -> Result<CallResult<T>, Fault<T>> {
+        let test_val = input.get(memory)?;
         if test_val == convert(0) {
-            Ok(CallResult::Jump(target.get(memory).unwrap_or_else(|| convert(0))))
+            Ok(CallResult::Jump(target.get(memory)?))
         } else {
             Ok(CallResult::Step(self.length()))
         }
     }
     
-    fn less_than(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut HashMap<T,T>) -> Result<CallResult<T>, String> {
-        let value1 = input1.get(memory).unwrap_or_else(|| convert(0));
-        let value2 = input2.get(memory).unwrap_or_else(|| convert(0));
+    fn less_than(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut dyn Memory<T>) -> Result<CallResult<T>, Fault<T>> {
+        let value1 = input1.get(memory)?;
+        let value2 = input2.get(memory)?;
         output.set(memory, if value1 < value2 { convert(1) } else { convert(0) }).and(Ok(CallResult::Step(self.length())))
     }
 
-    fn equals(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut HashMap<T,T>) -> Result<CallResult<T>, String> {
-        let value1 = input1.get(memory).unwrap_or_else(|| convert(0));
-        let value2 = input2.get(memory).unwrap_or_else(|| convert(0));
+    fn equals(&self, input1: &Argument<T>, input2: &Argument<T>, output: &Argument<T>, memory: &mut dyn Memory<T>) -> Result<CallResult<T>, Fault<T>> {
+        let value1 = input1.get(memory)?;
+        let value2 = input2.get(memory)?;
         output.set(memory, if value1 == value2 { convert(1) } else { convert(0) }).and(Ok(CallResult::Step(self.length())))
     }
 
-    fn adjust_relative_base(&self, input: &Argument<T>, memory: &mut HashMap<T,T>, relative_base: &mut T) -> Result<CallResult<T>, String> {
-        *relative_base = *relative_base + input.get(memory).unwrap_or_else(|| convert(0));
+    fn adjust_relative_base(&self, input: &Argument<T>, memory: &mut dyn Memory<T>, relative_base: &mut T, checked: bool) -> Result<CallResult<T>, Fault<T>> {
+        let adjustment = input.get(memory)?;
+        *relative_base = if checked { relative_base.checked_add(&adjustment).ok_or(Fault::Overflow)? } else { relative_base.clone() + adjustment };
         Ok(CallResult::Step(self.length()))
     }
 }
@@ -368,11 +832,17 @@ impl<T> Instruction<T> where T: Integer + Signed + Copy + FromPrimitive + Hash +
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::sync::mpsc::sync_channel;
     use std::thread;
+    use num::BigInt;
+
+    fn hash_with_indexes<T: Integer + Clone>(vec: Vec<T>) -> HashMap<usize,T> {
+        (0..).zip(vec).collect()
+    }
 
-    fn hash_with_indexes<T: Integer + Copy + FromPrimitive + Hash>(vec: Vec<T>) -> HashMap<T,T> {
-        (0..).zip(vec).map(|(k,v)| (convert(k), v.clone())).collect()
+    fn memory_map<T: Integer + Clone>(memory: &dyn Memory<T>) -> HashMap<usize,T> {
+        memory.cells().into_iter().collect()
     }
 
     #[test]
@@ -395,7 +865,7 @@ mod tests {
 
     #[test]
     fn test_new_instruction_stop() {
-        let instruction = Instruction::new(99, 0, vec![], &HashMap::new(), 0).unwrap();
+        let instruction = Instruction::new(99, 0, vec![], &HashMap::<usize, i32>::new(), 0).unwrap();
         assert_eq!(Instruction::Stop,
                    instruction);
     }
@@ -464,7 +934,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![2, 0, 0, 0, 99]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![2, 0, 0, 0, 99]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -473,7 +943,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![2, 3, 0, 6, 99]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![2, 3, 0, 6, 99]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -482,7 +952,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![2, 4, 4, 5, 99, 9801]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![2, 4, 4, 5, 99, 9801]), memory_map(computer.memory.as_ref()));
     }
     
     #[test]
@@ -493,7 +963,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(2, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![3, 2, 42]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![3, 2, 42]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -508,7 +978,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(2, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![3, 2, 42]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![3, 2, 42]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -517,7 +987,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(2, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![4, 2, 42]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![4, 2, 42]), memory_map(computer.memory.as_ref()));
         assert_eq!(vec![42], Vec::from(match computer.output {
             ComputerOutput::Queue(q) => q,
             _ => VecDeque::new()
@@ -532,7 +1002,7 @@ mod tests {
             let mut computer = Computer::new(vec![4, 2, 42], None, Some(ComputerOutput::Channel(tx)));
             computer.step().unwrap();
             assert_eq!(2, computer.loc);
-            assert_eq!(hash_with_indexes(vec![4, 2, 42]), computer.memory);
+            assert_eq!(hash_with_indexes(vec![4, 2, 42]), memory_map(computer.memory.as_ref()));
         });
 
         assert_eq!(42, rx.recv().unwrap());
@@ -545,7 +1015,7 @@ mod tests {
         let mut computer = Computer::new(vec![4, 2, 43], None, Some(ComputerOutput::Channel(tx)));
         computer.step().unwrap();
         assert_eq!(2, computer.loc);
-        assert_eq!(hash_with_indexes(vec![4, 2, 43]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![4, 2, 43]), memory_map(computer.memory.as_ref()));
         assert_eq!(43, computer.output().pop_front().unwrap());
     }
 
@@ -555,7 +1025,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(20, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![1105, 1, 20]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![1105, 1, 20]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -564,7 +1034,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(2, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![5, 0, 2]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![5, 0, 2]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -573,7 +1043,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(3, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![1105, 0, 20]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![1105, 0, 20]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -582,7 +1052,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(3, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![5, 3, 20, 0]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![5, 3, 20, 0]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -591,7 +1061,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(20, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![1106, 0, 20]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![1106, 0, 20]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -600,7 +1070,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(0, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![6, 3, 3, 0]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![6, 3, 3, 0]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -609,7 +1079,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(3, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![1106, 1, 20]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![1106, 1, 20]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -618,7 +1088,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![1, -1, 0, 0]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![1, -1, 0, 0]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -627,7 +1097,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![0, 10, 10, 0]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![0, 10, 10, 0]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -636,7 +1106,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![1, 42, 42, 0]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![1, 42, 42, 0]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -645,7 +1115,7 @@ mod tests {
         computer.step().unwrap();
         assert_eq!(4, computer.loc);
         assert_eq!(true, computer.running);
-        assert_eq!(hash_with_indexes(vec![0, 42, 43, 0]), computer.memory);
+        assert_eq!(hash_with_indexes(vec![0, 42, 43, 0]), memory_map(computer.memory.as_ref()));
     }
 
     #[test]
@@ -661,6 +1131,164 @@ mod tests {
         assert_eq!(30, computer.run().unwrap());
     }
 
+    #[test]
+    fn test_resume_needs_input() {
+        let mut computer = Computer::new(vec![3, 7, 4, 7, 1105, 1, 0, 0], None, None);
+        assert_eq!(RunStatus::NeedsInput, computer.resume().unwrap());
+        assert_eq!(0, computer.loc);
+        computer.push_input(5).unwrap();
+        assert_eq!(RunStatus::ProducedOutput(5), computer.resume().unwrap());
+        assert_eq!(RunStatus::NeedsInput, computer.resume().unwrap());
+        assert!(computer.output().is_empty());
+    }
+
+    #[test]
+    fn test_resume_after_step_back() {
+        let mut computer = Computer::new(vec![3, 5, 4, 5, 99, 0], None, None);
+        computer.push_input(7).unwrap();
+        computer.start_recording();
+        assert_eq!(RunStatus::ProducedOutput(7), computer.resume().unwrap());
+        computer.step_back();
+        computer.step_back();
+        assert_eq!(RunStatus::ProducedOutput(7), computer.resume().unwrap());
+
+        let (tx, rx) = sync_channel(1);
+        tx.send(8).unwrap();
+        drop(tx);
+        let mut computer = Computer::new(vec![3, 5, 4, 5, 99, 0], Some(ComputerInput::Channel(rx)), None);
+        computer.start_recording();
+        assert_eq!(RunStatus::ProducedOutput(8), computer.resume().unwrap());
+        computer.step_back();
+        computer.step_back();
+        assert_eq!(RunStatus::ProducedOutput(8), computer.resume().unwrap());
+        assert_eq!(RunStatus::Halted, computer.resume().unwrap());
+    }
+
+    #[test]
+    fn test_resume_halted() {
+        let mut computer = Computer::new(vec![104, 3, 99], None, None);
+        assert_eq!(RunStatus::ProducedOutput(3), computer.resume().unwrap());
+        assert_eq!(RunStatus::Halted, computer.resume().unwrap());
+        assert_eq!(RunStatus::Halted, computer.resume().unwrap());
+    }
+
+    #[test]
+    fn test_resume_feedback_loop() {
+        let program = vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+        let mut amplifiers: Vec<Computer<i32>> = [9, 8, 7, 6, 5].iter()
+            .map(|&phase| Computer::new(program.clone(), Some(ComputerInput::Queue(VecDeque::from(vec![phase]))), None))
+            .collect();
+        let mut signal = 0;
+        let mut halted = false;
+        while !halted {
+            for amplifier in amplifiers.iter_mut() {
+                amplifier.push_input(signal).unwrap();
+                match amplifier.resume().unwrap() {
+                    RunStatus::ProducedOutput(value) => signal = value,
+                    RunStatus::Halted => halted = true,
+                    RunStatus::NeedsInput => panic!("Amplifier stalled waiting for input.")
+                }
+            }
+        }
+        assert_eq!(139629729, signal);
+    }
+
+    #[test]
+    fn test_error_unknown_opcode() {
+        let mut computer = Computer::new(vec![1101, 1, 1, 5, 42, 0], None, None);
+        assert_eq!(Err(IntcodeError::UnknownOpcode { address: 4, instruction: 42 }), computer.run());
+    }
+
+    #[test]
+    fn test_error_bad_parameter_mode() {
+        let mut computer = Computer::new(vec![3001, 0, 0, 0], None, None);
+        assert_eq!(Err(IntcodeError::BadParameterMode { address: 0, instruction: 3001 }), computer.run());
+    }
+
+    #[test]
+    fn test_error_write_to_immediate() {
+        let mut computer = Computer::new(vec![11101, 0, 0, 0], None, None);
+        assert_eq!(Err(IntcodeError::WriteToImmediate { address: 0, instruction: 11101 }), computer.run());
+    }
+
+    #[test]
+    fn test_error_negative_address() {
+        let mut computer = Computer::new(vec![109, -5, 204, 1, 99], None, None);
+        assert_eq!(Err(IntcodeError::NegativeAddress { address: 2, instruction: 204, target: -4 }), computer.run());
+    }
+
+    #[test]
+    fn test_error_input_exhausted() {
+        let mut computer = Computer::new(vec![3, 0, 99], None, None);
+        let error = computer.run().unwrap_err();
+        assert_eq!(IntcodeError::InputExhausted { address: 0, instruction: 3 }, error);
+        assert_eq!("Failed to find an input value for instruction 3 at address 0.", error.to_string());
+    }
+
+    #[test]
+    fn test_error_channel_closed() {
+        let (tx, rx) = sync_channel(0);
+        drop(tx);
+        let mut computer = Computer::new(vec![3, 0, 99], Some(ComputerInput::Channel(rx)), None);
+        assert_eq!(Err(IntcodeError::ChannelClosed { address: 0, instruction: 3 }), computer.run());
+    }
+
+    #[test]
+    fn test_step_limit_is_resumable() {
+        // Counts down from 3 in [9], then halts.
+        let mut computer = Computer::new(vec![1001, 9, -1, 9, 1005, 9, 0, 99, 0, 3], None, None);
+        computer.set_limits(Limits { max_steps: Some(3), ..Limits::default() });
+        assert_eq!(Err(IntcodeError::StepLimitExceeded { address: 4, instruction: 1005, limit: 3 }), computer.run());
+        assert_eq!(3, computer.steps());
+        assert_eq!(Ok(1), computer.read(9));
+
+        computer.set_limits(Limits::default());
+        assert_eq!(Ok(1001), computer.run());
+        assert_eq!(Ok(0), computer.read(9));
+    }
+
+    #[test]
+    fn test_step_limit_stops_infinite_loop() {
+        let mut computer = Computer::new(vec![1105, 1, 0], None, None);
+        computer.set_limits(Limits { max_steps: Some(10_000), ..Limits::default() });
+        assert_eq!(Err(IntcodeError::StepLimitExceeded { address: 0, instruction: 1105, limit: 10_000 }), computer.run());
+        assert!(computer.is_running());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut computer = Computer::new(vec![1101, 1, 2, 1000, 99], None, None);
+        computer.set_limits(Limits { max_memory: Some(100), ..Limits::default() });
+        assert_eq!(Err(IntcodeError::MemoryLimitExceeded { address: 0, instruction: 1101, limit: 100 }), computer.run());
+        assert_eq!(4, computer.loc());
+        assert_eq!(Ok(3), computer.read(1000));
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut computer = Computer::new(vec![1105, 1, 0], None, None);
+        computer.set_limits(Limits::timeout(Duration::from_millis(20)));
+        assert_eq!(Err(IntcodeError::DeadlineExceeded { address: 0, instruction: 1105 }), computer.run());
+        assert!(computer.steps() > 0);
+    }
+
+    #[test]
+    fn test_limits_apply_to_resume() {
+        let mut computer = Computer::new(vec![104, 1, 1105, 1, 0], None, None);
+        computer.set_limits(Limits { max_steps: Some(4), ..Limits::default() });
+        assert_eq!(Ok(RunStatus::ProducedOutput(1)), computer.resume());
+        assert_eq!(Ok(RunStatus::ProducedOutput(1)), computer.resume());
+        assert_eq!(Err(IntcodeError::StepLimitExceeded { address: 0, instruction: 104, limit: 4 }), computer.resume());
+    }
+
+    #[test]
+    fn test_computers_can_be_sent_to_other_threads() {
+        fn assert_send<S: Send>() {}
+        assert_send::<Computer<i64>>();
+        assert_send::<Computer<BigInt>>();
+        assert_send::<snapshot::Snapshot<i64>>();
+    }
+
     #[test]
     fn test_64bit() {
         let mut computer: Computer<i64> = Computer::new(vec![104i64,1125899906842624i64,99i64], None, None);
@@ -670,4 +1298,75 @@ mod tests {
             _ => VecDeque::new()
         }));
     }
+
+    #[test]
+    fn test_overflow_checks() {
+        let programs: Vec<Vec<i32>> = vec![
+            vec![1101, i32::MAX, 1, 5, 99, 0],
+            vec![1102, 100000, 100000, 5, 99, 0],
+            vec![109, i32::MAX, 109, 1, 99],
+        ];
+        for (program, address) in programs.into_iter().zip(vec![0, 0, 2]) {
+            let instruction = program[address as usize];
+            let mut computer = Computer::new(program, None, None);
+            computer.set_overflow_checks(true);
+            assert_eq!(Err(IntcodeError::Overflow { address, instruction }), computer.run());
+            assert_eq!(address, computer.loc());
+        }
+    }
+
+    #[test]
+    fn test_overflow_checks_allow_large_values() {
+        let mut computer: Computer<i64> = Computer::new(vec![1102, 3037000499, 3037000499, 7, 4, 7, 99, 0], None, None);
+        computer.set_overflow_checks(true);
+        computer.run().unwrap();
+        assert_eq!(vec![9223372030926249001], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_big_integers() {
+        let program = "1102,9223372036854775807,9223372036854775807,7,4,7,99,0";
+        let program: Vec<BigInt> = crate::util::read_comma_separated_integers(program.as_bytes()).unwrap();
+        let mut computer = Computer::new(program, None, None);
+        computer.set_overflow_checks(true);
+        computer.run().unwrap();
+        assert_eq!(vec!["85070591730234615847396907784232501249".parse::<BigInt>().unwrap()], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_self_modifying_code_is_decoded_again() {
+        // Outputs the parameter of its own output instruction, incrementing it each time round.
+        let mut computer = Computer::new(vec![104, 1, 1001, 1, 1, 1, 1007, 1, 4, 14, 1005, 14, 0, 99, 0], None, None);
+        computer.run().unwrap();
+        assert_eq!(vec![1, 2, 3], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_cached_instruction_uses_current_relative_base() {
+        // Moves the relative base along three cells, outputting each.
+        let mut computer = Computer::new(vec![109, 1, 204, 12, 1001, 12, -1, 12, 1005, 12, 0, 99, 3, 7, 8, 9], None, None);
+        computer.run().unwrap();
+        assert_eq!(vec![7, 8, 9], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_writes_outside_execution_invalidate_decoded_code() {
+        let mut computer = Computer::new(vec![104, 1, 1105, 1, 0], None, None);
+        assert_eq!(Ok(RunStatus::ProducedOutput(1)), computer.resume());
+        computer.write(1, 5).unwrap();
+        assert_eq!(Ok(RunStatus::ProducedOutput(5)), computer.resume());
+    }
+
+    #[test]
+    fn test_step_back_invalidates_decoded_code() {
+        let mut computer = Computer::new(vec![104, 1, 1101, 7, 0, 1, 1105, 1, 0], None, None);
+        computer.start_recording();
+        assert_eq!(Ok(RunStatus::ProducedOutput(1)), computer.resume());
+        assert_eq!(Ok(RunStatus::ProducedOutput(7)), computer.resume());
+        for _ in 0..3 {
+            computer.step_back();
+        }
+        computer.set_loc(0);
+        assert_eq!(Ok(RunStatus::ProducedOutput(1)), computer.resume());
+    }
 }
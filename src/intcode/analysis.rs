@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{ArgumentKind, Instruction};
+use super::disassembler::decode;
+use super::memory::MemoryKind;
+
+/// How control leaves a basic block.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Exit {
+    /// The block ends with a halt instruction.
+    Halt,
+    /// Control always continues at the address, either by falling through into the next block or
+    /// through a jump whose condition is an immediate value.
+    Goto(usize),
+    /// A conditional jump with an immediate target.
+    Branch { taken: usize, not_taken: usize },
+    /// A jump whose target is read from memory, so can't be known without running the program.
+    /// `not_taken` is `None` when the jump always happens.
+    Computed { not_taken: Option<usize> },
+    /// The block runs into a word that doesn't start a valid instruction.
+    Invalid(usize),
+}
+
+impl Exit {
+    /// The addresses of the blocks control can pass to.
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Exit::Halt | Exit::Invalid(_) => vec![],
+            Exit::Goto(target) => vec![*target],
+            Exit::Branch { taken, not_taken } => vec![*taken, *not_taken],
+            Exit::Computed { not_taken } => not_taken.iter().cloned().collect(),
+        }
+    }
+}
+
+/// A straight-line run of instructions, entered only at the top and left only at the bottom.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Block<T> {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction<T>)>,
+    pub exit: Exit,
+}
+
+/// An instruction that writes to an address holding part of a reachable instruction.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CodeWrite {
+    pub address: usize,
+    pub target: usize,
+}
+
+/// The control-flow graph of a program image, found without running it.
+///
+/// Decoding starts at address zero and follows every path that can be known statically: falling
+/// through to the next instruction, and the immediate-mode targets of `jt` and `jf`. A jump whose
+/// condition is an immediate value always or never happens, and is treated that way. Jumps to
+/// targets held in memory are recorded as computed, and the code they lead to isn't explored.
+///
+/// Only position-mode writes are checked for self-modification, as the destination of a
+/// relative-mode write depends on the relative base at the time.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ControlFlowGraph<T> {
+    pub blocks: BTreeMap<usize, Block<T>>,
+    pub code_writes: Vec<CodeWrite>,
+}
+
+impl<T> ControlFlowGraph<T>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    pub fn new(program: &[T]) -> Self {
+        let memory = MemoryKind::Dense.load(program.to_vec());
+        let mut decoded = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut pending = vec![0];
+        leaders.insert(0);
+
+        while let Some(address) = pending.pop() {
+            if decoded.contains_key(&address) {
+                continue;
+            }
+            let entry = if address < program.len() { decode(program, address, memory.as_ref()) } else { None };
+            if let Some((instruction, length)) = &entry {
+                match exit(instruction, address + length) {
+                    Some(exit) => {
+                        let successors = exit.successors();
+                        leaders.extend(successors.iter().cloned());
+                        pending.extend(successors);
+                    },
+                    None => pending.push(address + length)
+                }
+            }
+            decoded.insert(address, entry);
+        }
+
+        let code_writes = code_writes(&decoded);
+        let blocks = leaders.iter().map(|&start| {
+            let mut instructions = vec![];
+            let mut address = start;
+            let exit = loop {
+                let (instruction, length) = match decoded.remove(&address).flatten() {
+                    Some(entry) => entry,
+                    None => break Exit::Invalid(address)
+                };
+                let next = address + length;
+                let exit = exit(&instruction, next);
+                instructions.push((address, instruction));
+                match exit {
+                    Some(exit) => break exit,
+                    None if leaders.contains(&next) => break Exit::Goto(next),
+                    None => address = next
+                }
+            };
+            (start, Block { start, instructions, exit })
+        }).collect();
+
+        ControlFlowGraph { blocks, code_writes }
+    }
+
+    /// The addresses of the jump instructions whose targets are read from memory.
+    pub fn computed_jumps(&self) -> Vec<usize> {
+        self.blocks.values()
+            .filter(|block| matches!(block.exit, Exit::Computed { .. }))
+            .filter_map(|block| block.instructions.last().map(|(address, _)| *address))
+            .collect()
+    }
+
+    /// Formats the graph in Graphviz DOT format, one node per block. Blocks containing
+    /// self-modifying writes are drawn in red, and computed jumps lead to a `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph program {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                write!(label, "{:>5}: {}\\l", address, instruction).unwrap();
+            }
+            match &block.exit {
+                Exit::Halt | Exit::Goto(_) | Exit::Branch { .. } | Exit::Computed { .. } => {},
+                Exit::Invalid(address) => write!(label, "{:>5}: invalid\\l", address).unwrap(),
+            }
+            let modifies_code = block.instructions.iter()
+                .any(|(address, _)| self.code_writes.iter().any(|write| write.address == *address));
+            let style = if modifies_code { ", color=red" } else { "" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+        }
+
+        for block in self.blocks.values() {
+            match &block.exit {
+                Exit::Halt | Exit::Invalid(_) => {},
+                Exit::Goto(target) => writeln!(dot, "    b{} -> b{};", block.start, target).unwrap(),
+                Exit::Branch { taken, not_taken } => {
+                    writeln!(dot, "    b{} -> b{} [label=\"jump\"];", block.start, taken).unwrap();
+                    writeln!(dot, "    b{} -> b{} [label=\"next\"];", block.start, not_taken).unwrap();
+                },
+                Exit::Computed { not_taken } => {
+                    writeln!(dot, "    b{} -> computed [label=\"jump\", style=dashed];", block.start).unwrap();
+                    if let Some(not_taken) = not_taken {
+                        writeln!(dot, "    b{} -> b{} [label=\"next\"];", block.start, not_taken).unwrap();
+                    }
+                }
+            }
+        }
+        if !self.computed_jumps().is_empty() {
+            dot.push_str("    computed [shape=diamond, label=\"?\"];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Works out whether an instruction ends a block, given the address of the instruction after it.
+fn exit<T: Integer + Signed + Clone + CheckedAdd + ToPrimitive + fmt::Display>(instruction: &Instruction<T>, next: usize) -> Option<Exit> {
+    let (condition, target, jump_if) = match instruction {
+        Instruction::Stop => return Some(Exit::Halt),
+        Instruction::JumpIfTrue(condition, target) => (condition, target, true),
+        Instruction::JumpIfFalse(condition, target) => (condition, target, false),
+        _ => return None
+    };
+
+    let always = match condition.kind() {
+        ArgumentKind::Immediate => Some(condition.value().is_zero() != jump_if),
+        _ => None
+    };
+    let target = match target.kind() {
+        ArgumentKind::Immediate => target.value().to_usize(),
+        _ => None
+    };
+    match (always, target) {
+        (Some(false), _) => None,
+        (Some(true), Some(target)) => Some(Exit::Goto(target)),
+        (Some(true), None) => Some(Exit::Computed { not_taken: None }),
+        (None, Some(target)) => Some(Exit::Branch { taken: target, not_taken: next }),
+        (None, None) => Some(Exit::Computed { not_taken: Some(next) }),
+    }
+}
+
+/// Finds the position-mode writes that land inside a reachable instruction.
+fn code_writes<T>(decoded: &BTreeMap<usize, Option<(Instruction<T>, usize)>>) -> Vec<CodeWrite>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let code: Vec<(usize, usize)> = decoded.iter()
+        .filter_map(|(&address, entry)| entry.as_ref().map(|(_, length)| (address, address + length)))
+        .collect();
+
+    decoded.iter()
+        .filter_map(|(&address, entry)| {
+            let argument = entry.as_ref()?.0.written_argument()?;
+            if *argument.kind() != ArgumentKind::Position {
+                return None;
+            }
+            let target = argument.value().to_usize()?;
+            code.iter()
+                .any(|&(start, end)| (start..end).contains(&target))
+                .then_some(CodeWrite { address, target })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    #[test]
+    fn test_straight_line() {
+        let graph = ControlFlowGraph::new(&[1, 0, 0, 0, 99]);
+        assert_eq!(1, graph.blocks.len());
+        assert_eq!(2, graph.blocks[&0].instructions.len());
+        assert_eq!(Exit::Halt, graph.blocks[&0].exit);
+    }
+
+    #[test]
+    fn test_loop() {
+        let program = assemble::<i64>("
+            loop:   add [n], #-1, [n]
+                    out [n]
+                    jt [n], #loop
+                    hlt
+            n:      .data 3").unwrap();
+        let graph = ControlFlowGraph::new(&program);
+        assert_eq!(vec![0, 9], graph.blocks.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(Exit::Branch { taken: 0, not_taken: 9 }, graph.blocks[&0].exit);
+        assert_eq!(Exit::Halt, graph.blocks[&9].exit);
+        assert!(graph.code_writes.is_empty());
+    }
+
+    #[test]
+    fn test_immediate_conditions() {
+        let program = assemble::<i64>("
+                    jt #1, #skip
+                    out #1
+            skip:   jf #1, #0
+                    hlt").unwrap();
+        let graph = ControlFlowGraph::new(&program);
+        assert_eq!(vec![0, 5], graph.blocks.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(Exit::Goto(5), graph.blocks[&0].exit);
+        assert_eq!(2, graph.blocks[&5].instructions.len());
+    }
+
+    #[test]
+    fn test_computed_jump() {
+        let program = assemble::<i64>("
+                    in [t]
+                    jt #1, [t]
+            t:      .data 0").unwrap();
+        let graph = ControlFlowGraph::new(&program);
+        assert_eq!(Exit::Computed { not_taken: None }, graph.blocks[&0].exit);
+        assert_eq!(vec![2], graph.computed_jumps());
+        assert!(graph.to_dot().contains("b0 -> computed"));
+    }
+
+    #[test]
+    fn test_self_modifying_write() {
+        let graph = ControlFlowGraph::new(&[1101, 1, 0, 6, 1105, 1, 99, 99]);
+        assert_eq!(vec![CodeWrite { address: 0, target: 6 }], graph.code_writes);
+        assert!(graph.to_dot().contains("color=red"));
+    }
+
+    #[test]
+    fn test_invalid_target() {
+        let graph = ControlFlowGraph::new(&[1006, 7, 6, 99, 1105, 1, 50, 0]);
+        assert_eq!(Exit::Branch { taken: 6, not_taken: 3 }, graph.blocks[&0].exit);
+        assert_eq!(Exit::Invalid(6), graph.blocks[&6].exit);
+        assert!(graph.blocks[&6].instructions.is_empty());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let graph = ControlFlowGraph::new(&[1005, 5, 4, 99, 99, 0]);
+        assert_eq!(
+            "digraph program {\n    node [shape=box, fontname=\"monospace\"];\n\
+             \x20   b0 [label=\"    0: jt [5], #4\\l\"];\n\
+             \x20   b3 [label=\"    3: hlt\\l\"];\n\
+             \x20   b4 [label=\"    4: hlt\\l\"];\n\
+             \x20   b0 -> b4 [label=\"jump\"];\n\
+             \x20   b0 -> b3 [label=\"next\"];\n\
+             }\n",
+            graph.to_dot());
+    }
+}
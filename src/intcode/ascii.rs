@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Write};
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerOutput, RunStatus};
+
+/// Converts text to input values, one per character.
+pub fn encode<T: FromPrimitive>(text: &str) -> Result<Vec<T>, String> {
+    text.chars()
+        .map(|c| if c.is_ascii() { T::from_u8(c as u8) } else { None }
+            .ok_or_else(|| format!("'{}' is not an ASCII character.", c)))
+        .collect()
+}
+
+/// Converts output values to text, setting aside any that aren't ASCII characters.
+pub fn decode<T: ToPrimitive, I: IntoIterator<Item = T>>(values: I) -> AsciiOutput<T> {
+    let mut output = AsciiOutput::new();
+    for value in values {
+        output.push(value);
+    }
+    output
+}
+
+/// Output from a program that speaks ASCII.
+///
+/// Such programs usually report their result as a single value too large to be a character,
+/// which is kept in `answers` rather than being mangled into the text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct AsciiOutput<T> {
+    pub text: String,
+    pub answers: Vec<T>,
+}
+
+impl<T: ToPrimitive> AsciiOutput<T> {
+    pub fn new() -> Self {
+        AsciiOutput { text: String::new(), answers: vec![] }
+    }
+
+    /// Adds an output value, returning the character it stands for if it's ASCII.
+    pub fn push(&mut self, value: T) -> Option<char> {
+        match value.to_u8().filter(u8::is_ascii) {
+            Some(byte) => {
+                self.text.push(byte as char);
+                Some(byte as char)
+            },
+            None => {
+                self.answers.push(value);
+                None
+            }
+        }
+    }
+}
+
+impl<T: ToPrimitive> Default for AsciiOutput<T> {
+    fn default() -> Self {
+        AsciiOutput::new()
+    }
+}
+
+impl<T> Computer<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Sends a line of text as input, followed by a newline.
+    pub fn push_line(&mut self, line: &str) -> Result<(), String> {
+        for value in encode(line)? {
+            self.push_input(value)?;
+        }
+        self.push_input(T::from_u8(b'\n').unwrap())
+    }
+
+    /// Removes the output produced so far and converts it to text.
+    pub fn take_ascii_output(&mut self) -> AsciiOutput<T> {
+        match &mut self.output {
+            ComputerOutput::Queue(q) => decode(q.drain(..)),
+            ComputerOutput::Channel(_) => decode(self.alt_output.drain(..))
+        }
+    }
+}
+
+/// Runs a program that speaks ASCII, writing its text to `output` as it's produced and reading a
+/// line from `input` whenever it's waiting for more. Returns the values that weren't characters.
+pub fn run_interactive<T, R, W>(computer: &mut Computer<T>, input: R, mut output: W) -> Result<Vec<T>, Box<dyn Error>>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + fmt::Debug + Send + 'static,
+    R: BufRead,
+    W: Write,
+{
+    let mut lines = input.lines();
+    let mut answers = vec![];
+    loop {
+        match computer.resume()? {
+            RunStatus::ProducedOutput(value) => match value.to_u8().filter(u8::is_ascii) {
+                Some(byte) => output.write_all(&[byte])?,
+                None => answers.push(value)
+            },
+            RunStatus::NeedsInput => {
+                output.flush()?;
+                let line = lines.next().ok_or("Input ended while the program was waiting for more.")??;
+                computer.push_line(&line)?;
+            },
+            RunStatus::Halted => break
+        }
+    }
+    output.flush()?;
+    Ok(answers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::intcode::ComputerInput;
+    use crate::intcode::assembler::assemble;
+
+    /// Echoes each line in upper case until it reads an empty line, then outputs 1000.
+    const SHOUT: &str = "
+        loop:   in [c]
+                eq [c], #10, [t]
+                jt [t], #newline
+                lt [c], #97, [t]
+                jt [t], #echo
+                add [c], #-32, [c]
+        echo:   out [c]
+                add #0, #0, [start]
+                jt #1, #loop
+        newline: jt [start], #done
+                out #10
+                add #1, #0, [start]
+                jt #1, #loop
+        done:   out #1000
+                hlt
+        c:      .data 0
+        t:      .data 0
+        start:  .data 1";
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(Ok(vec![72, 105, 10]), encode::<i64>("Hi\n"));
+        assert!(encode::<i64>("café").is_err());
+    }
+
+    #[test]
+    fn test_decode_separates_answers() {
+        let output = decode(vec![35, 46, 10, 19349722, -1]);
+        assert_eq!("#.\n", output.text);
+        assert_eq!(vec![19349722, -1], output.answers);
+    }
+
+    #[test]
+    fn test_push_line_and_take_output() {
+        let program = assemble::<i64>(SHOUT).unwrap();
+        let mut computer = Computer::new(program, Some(ComputerInput::Queue(VecDeque::new())), None);
+        computer.push_line("abc").unwrap();
+        computer.push_line("").unwrap();
+        computer.run().unwrap();
+        assert_eq!(AsciiOutput { text: String::from("ABC\n"), answers: vec![1000] }, computer.take_ascii_output());
+        assert!(computer.output().is_empty());
+    }
+
+    #[test]
+    fn test_run_interactive() {
+        let program = assemble::<i64>(SHOUT).unwrap();
+        let mut computer = Computer::new(program, None, None);
+        let mut output = vec![];
+        let answers = run_interactive(&mut computer, "Hello, world\nok\n\n".as_bytes(), &mut output).unwrap();
+        assert_eq!("HELLO, WORLD\nOK\n", String::from_utf8(output).unwrap());
+        assert_eq!(vec![1000], answers);
+    }
+
+    #[test]
+    fn test_run_interactive_input_ends() {
+        let program = assemble::<i64>(SHOUT).unwrap();
+        let mut computer = Computer::new(program, None, None);
+        assert!(run_interactive(&mut computer, "abc\n".as_bytes(), vec![]).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use num::{Integer, Signed, FromPrimitive};
+
+/// The operations understood by the assembler, as (mnemonic, opcode, parameter count, index of
+/// the parameter that is written to, if any).
+const OPERATIONS: [(&str, usize, usize, Option<usize>); 10] = [
+    ("add", 1, 3, Some(2)),
+    ("mul", 2, 3, Some(2)),
+    ("in", 3, 1, Some(0)),
+    ("out", 4, 1, None),
+    ("jt", 5, 2, None),
+    ("jf", 6, 2, None),
+    ("lt", 7, 3, Some(2)),
+    ("eq", 8, 3, Some(2)),
+    ("arb", 9, 1, None),
+    ("hlt", 99, 0, None),
+];
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Term<T> {
+    Literal(T),
+    Label(String),
+}
+
+/// A sum of literals and label addresses, e.g. `buffer+2`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct Expr<T> {
+    terms: Vec<(bool, Term<T>)>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Operand<T> {
+    Position(Expr<T>),
+    Immediate(Expr<T>),
+    Relative(Expr<T>),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Statement<T> {
+    Operation(usize, Vec<Operand<T>>),
+    Data(Vec<Expr<T>>),
+    Zero(usize),
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct Line<T> {
+    number: usize,
+    labels: Vec<String>,
+    statement: Option<Statement<T>>,
+}
+
+impl<T> Statement<T> {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Operation(_, operands) => operands.len() + 1,
+            Statement::Data(values) => values.len(),
+            Statement::Zero(count) => *count,
+        }
+    }
+}
+
+/// Assembles Intcode assembly source into a program that can be loaded by `Computer::new`.
+///
+/// Each line holds any number of `label:` prefixes followed by an optional statement, and
+/// anything after a `;` is a comment. Statements are either an operation (`add`, `mul`, `in`,
+/// `out`, `jt`, `jf`, `lt`, `eq`, `arb`, `hlt`) with comma-separated operands, `.data` followed
+/// by comma-separated values, or `.zero` followed by a count of zero words to reserve.
+///
+/// Operands are written `[x]` for position mode, `#x` for immediate mode and `rb+x` (or `rb-x`)
+/// for relative mode, where `x` is an integer, a label or a sum of these such as `buffer+2`.
+pub fn assemble<T>(source: &str) -> Result<Vec<T>, String>
+where
+    T: Integer + Signed + Clone + FromPrimitive + FromStr,
+{
+    let lines = source.lines()
+        .enumerate()
+        .map(|(index, text)| parse_line(index + 1, text))
+        .collect::<Result<Vec<Line<T>>, String>>()?;
+
+    let mut labels = HashMap::new();
+    let mut address = 0;
+    for line in &lines {
+        for label in &line.labels {
+            if labels.insert(label.clone(), address).is_some() {
+                return Err(format!("Line {}: label '{}' is defined more than once.", line.number, label));
+            }
+        }
+        address += line.statement.as_ref().map_or(0, |s| s.size());
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for line in &lines {
+        let error = |e: String| format!("Line {}: {}", line.number, e);
+        match &line.statement {
+            Some(Statement::Operation(opcode, operands)) => {
+                let mut code = *opcode;
+                let mut multiplier = 100;
+                let mut values = vec![];
+                for operand in operands {
+                    let (mode, expr) = match operand {
+                        Operand::Position(e) => (0, e),
+                        Operand::Immediate(e) => (1, e),
+                        Operand::Relative(e) => (2, e),
+                    };
+                    code += mode * multiplier;
+                    multiplier *= 10;
+                    values.push(evaluate(expr, &labels).map_err(error)?);
+                }
+                program.push(convert(code)?);
+                program.extend(values);
+            },
+            Some(Statement::Data(values)) => {
+                for value in values {
+                    program.push(evaluate(value, &labels).map_err(error)?);
+                }
+            },
+            Some(Statement::Zero(count)) => program.extend((0..*count).map(|_| T::zero())),
+            None => (),
+        }
+    }
+
+    Ok(program)
+}
+
+fn convert<T: FromPrimitive>(value: usize) -> Result<T, String> {
+    T::from_usize(value).ok_or_else(|| format!("Value {} is out of range.", value))
+}
+
+fn evaluate<T>(expr: &Expr<T>, labels: &HashMap<String, usize>) -> Result<T, String>
+where
+    T: Integer + Signed + Clone + FromPrimitive,
+{
+    expr.terms.iter().try_fold(T::zero(), |acc, (negative, term)| {
+        let value = match term {
+            Term::Literal(x) => x.clone(),
+            Term::Label(name) => match labels.get(name) {
+                Some(&address) => convert(address)?,
+                None => return Err(format!("undefined label '{}'.", name)),
+            },
+        };
+        Ok(if *negative { acc - value } else { acc + value })
+    })
+}
+
+fn parse_line<T: Integer + Signed + FromStr>(number: usize, text: &str) -> Result<Line<T>, String> {
+    let error = |e: String| format!("Line {}: {}", number, e);
+    let mut rest = text.split(';').next().unwrap_or("").trim();
+    let mut labels = vec![];
+
+    while let Some(index) = rest.find(':') {
+        let label = rest[..index].trim();
+        if !is_identifier(label) {
+            break;
+        }
+        labels.push(String::from(label));
+        rest = rest[index + 1..].trim();
+    }
+
+    let statement = if rest.is_empty() {
+        None
+    } else {
+        Some(parse_statement(rest).map_err(error)?)
+    };
+
+    Ok(Line { number, labels, statement })
+}
+
+fn parse_statement<T: Integer + Signed + FromStr>(text: &str) -> Result<Statement<T>, String> {
+    let (name, arguments) = match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    };
+    let arguments: Vec<&str> = if arguments.is_empty() {
+        vec![]
+    } else {
+        arguments.split(',').map(|a| a.trim()).collect()
+    };
+    let name = name.to_lowercase();
+
+    match name.as_str() {
+        ".data" => {
+            if arguments.is_empty() {
+                return Err(String::from(".data requires at least one value."));
+            }
+            Ok(Statement::Data(arguments.iter().map(|a| parse_expr(a)).collect::<Result<_, _>>()?))
+        },
+        ".zero" => match arguments.as_slice() {
+            [count] => count.parse()
+                .map(Statement::Zero)
+                .map_err(|_| format!("invalid .zero count '{}'.", count)),
+            _ => Err(String::from(".zero requires a single count.")),
+        },
+        _ => {
+            let &(_, opcode, parameters, written) = OPERATIONS.iter()
+                .find(|(mnemonic, _, _, _)| *mnemonic == name)
+                .ok_or_else(|| format!("unknown operation '{}'.", name))?;
+            if arguments.len() != parameters {
+                return Err(format!("{} takes {} operand(s), but got {}.", name, parameters, arguments.len()));
+            }
+            let operands = arguments.iter().map(|a| parse_operand(a)).collect::<Result<Vec<_>, _>>()?;
+            if let Some(index) = written {
+                if let Operand::Immediate(_) = operands[index] {
+                    return Err(format!("operand {} of {} is written to, so cannot be immediate.", index + 1, name));
+                }
+            }
+            Ok(Statement::Operation(opcode, operands))
+        }
+    }
+}
+
+fn parse_operand<T: Integer + Signed + FromStr>(text: &str) -> Result<Operand<T>, String> {
+    if let Some(rest) = text.strip_prefix('#') {
+        Ok(Operand::Immediate(parse_expr(rest)?))
+    } else if text.starts_with('[') && text.ends_with(']') {
+        Ok(Operand::Position(parse_expr(&text[1..text.len() - 1])?))
+    } else if let Some(rest) = text.strip_prefix("rb") {
+        let rest = rest.trim();
+        if rest.is_empty() {
+            Ok(Operand::Relative(Expr { terms: vec![] }))
+        } else if rest.starts_with('+') || rest.starts_with('-') {
+            Ok(Operand::Relative(parse_expr(rest)?))
+        } else {
+            Err(format!("invalid relative operand '{}'.", text))
+        }
+    } else {
+        Err(format!("invalid operand '{}': expected [x], #x or rb+x.", text))
+    }
+}
+
+fn parse_expr<T: Integer + Signed + FromStr>(text: &str) -> Result<Expr<T>, String> {
+    let mut terms = vec![];
+    let mut negative = false;
+    let mut current = String::new();
+
+    let mut push = |negative: bool, token: &str| -> Result<(), String> {
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(format!("invalid expression '{}'.", text.trim()));
+        }
+        let term = if is_identifier(token) {
+            Term::Label(String::from(token))
+        } else {
+            Term::Literal(token.parse().map_err(|_| format!("invalid value '{}'.", token))?)
+        };
+        terms.push((negative, term));
+        Ok(())
+    };
+
+    for (index, c) in text.char_indices() {
+        if (c == '+' || c == '-') && !current.trim().is_empty() {
+            push(negative, &current)?;
+            current.clear();
+            negative = c == '-';
+        } else if (c == '+' || c == '-') && index == text.len() - text.trim_start().len() {
+            negative = c == '-';
+        } else {
+            current.push(c);
+        }
+    }
+    push(negative, &current)?;
+
+    Ok(Expr { terms })
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::intcode::{Computer, ComputerInput};
+
+    #[test]
+    fn test_assemble_modes() {
+        let program: Vec<i32> = assemble("add [4], #3, rb+2\nhlt").unwrap();
+        assert_eq!(vec![21001, 4, 3, 2, 99], program);
+    }
+
+    #[test]
+    fn test_assemble_negative_relative() {
+        let program: Vec<i32> = assemble("out rb-3").unwrap();
+        assert_eq!(vec![204, -3], program);
+    }
+
+    #[test]
+    fn test_assemble_labels_and_data() {
+        let source = "
+            ; Double the input and output it.
+            start:  in [value]
+                    mul [value], #2, [value]
+                    out [value]
+                    hlt
+            value:  .data 0
+            table:  .data start, value+1, -4
+                    .zero 2";
+        let program: Vec<i32> = assemble(source).unwrap();
+        assert_eq!(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0, 0, 10, -4, 0, 0], program);
+    }
+
+    #[test]
+    fn test_assemble_runs() {
+        let source = "
+            loop:   in [n]
+                    eq [n], #0, [done]
+                    jt [done], #end
+                    out [n]
+                    jt #1, #loop
+            end:    hlt
+            n:      .data 0
+            done:   .data 0";
+        let program: Vec<i64> = assemble(source).unwrap();
+        let input = VecDeque::from(vec![7, 8, 0]);
+        let mut computer = Computer::new(program, Some(ComputerInput::Queue(input)), None);
+        computer.run().unwrap();
+        assert_eq!(vec![7, 8], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_assemble_unknown_operation() {
+        let result: Result<Vec<i32>, String> = assemble("hlt\nfoo [1]");
+        assert_eq!(Err(String::from("Line 2: unknown operation 'foo'.")), result);
+    }
+
+    #[test]
+    fn test_assemble_undefined_label() {
+        let result: Result<Vec<i32>, String> = assemble("out [missing]");
+        assert_eq!(Err(String::from("Line 1: undefined label 'missing'.")), result);
+    }
+
+    #[test]
+    fn test_assemble_duplicate_label() {
+        let result: Result<Vec<i32>, String> = assemble("a: hlt\na: hlt");
+        assert_eq!(Err(String::from("Line 2: label 'a' is defined more than once.")), result);
+    }
+
+    #[test]
+    fn test_assemble_immediate_write() {
+        let result: Result<Vec<i32>, String> = assemble("in #3");
+        assert_eq!(Err(String::from("Line 1: operand 1 of in is written to, so cannot be immediate.")), result);
+    }
+
+    #[test]
+    fn test_assemble_wrong_operand_count() {
+        let result: Result<Vec<i32>, String> = assemble("add [1], [2]");
+        assert_eq!(Err(String::from("Line 1: add takes 3 operand(s), but got 2.")), result);
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, CheckedSub, FromPrimitive, ToPrimitive};
+
+use super::{Computer, IntcodeError};
+use super::expression::{Comparison, Expression};
+
+/// A breakpoint, stopping the computer before it executes an instruction.
+///
+/// A breakpoint with an address stops when the computer reaches that address, and one with a
+/// condition stops when the condition is true. If both are given, both must hold; a breakpoint
+/// with only a condition is checked before every instruction.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Breakpoint<T> {
+    pub address: Option<T>,
+    pub condition: Option<Expression<T>>,
+}
+
+/// The memory accesses a watchpoint stops on.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Watch<T> {
+    /// An instruction reads one of its parameters from the memory.
+    Read,
+    /// An instruction writes its result to the memory.
+    Write,
+    /// Either of the above.
+    Access,
+    /// An instruction writes a value to the memory that satisfies the comparison.
+    Value(Comparison, T),
+}
+
+/// A watchpoint, stopping the computer after an instruction accesses an address in a range.
+///
+/// Only the parameters of instructions are watched: fetching an instruction from a watched address
+/// does not count as a read.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Watchpoint<T> {
+    /// The first address watched.
+    pub start: T,
+    /// The last address watched.
+    pub end: T,
+    pub watch: Watch<T>,
+}
+
+impl<T: Ord> Watchpoint<T> {
+    fn covers(&self, address: &T) -> bool {
+        self.start <= *address && *address <= self.end
+    }
+}
+
+/// An entry in a `BreakpointSet`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Point<T> {
+    Breakpoint(Breakpoint<T>),
+    Watchpoint(Watchpoint<T>),
+}
+
+impl<T: fmt::Display + PartialEq> fmt::Display for Point<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Point::Breakpoint(Breakpoint { address, condition }) => {
+                write!(f, "break")?;
+                if let Some(a) = address {
+                    write!(f, " at {}", a)?;
+                }
+                if let Some(c) = condition {
+                    write!(f, " if {}", c)?;
+                }
+                Ok(())
+            },
+            Point::Watchpoint(Watchpoint { start, end, watch }) => {
+                let range = if start == end { format!("[{}]", start) } else { format!("[{}..{}]", start, end) };
+                match watch {
+                    Watch::Read => write!(f, "watch read {}", range),
+                    Watch::Write => write!(f, "watch write {}", range),
+                    Watch::Access => write!(f, "watch access {}", range),
+                    Watch::Value(comparison, value) => write!(f, "watch {} {} {}", range, comparison, value)
+                }
+            }
+        }
+    }
+}
+
+/// The reason that a `BreakpointSet` stopped a computer.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Hit<T> {
+    /// The breakpoint with the given id was reached; the computer is at `address`.
+    Breakpoint { id: usize, address: T },
+    /// An instruction read `value` from a watched address.
+    Read { id: usize, address: T, value: T },
+    /// An instruction wrote to a watched address, replacing `old` with `new`.
+    Write { id: usize, address: T, old: T, new: T },
+}
+
+impl<T: fmt::Display> fmt::Display for Hit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hit::Breakpoint { address, .. } => write!(f, "Breakpoint at {}.", address),
+            Hit::Read { id, address, value } => write!(f, "Watchpoint {}: read {} from [{}].", id, value, address),
+            Hit::Write { id, address, old, new } => write!(f, "Watchpoint {}: [{}] changed from {} to {}.", id, address, old, new)
+        }
+    }
+}
+
+/// A numbered collection of breakpoints and watchpoints, which can run a computer until one of
+/// them is hit.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BreakpointSet<T> {
+    points: BTreeMap<usize, Point<T>>,
+    next_id: usize,
+}
+
+impl<T> Default for BreakpointSet<T> {
+    fn default() -> Self {
+        BreakpointSet { points: BTreeMap::new(), next_id: 1 }
+    }
+}
+
+impl<T> BreakpointSet<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedSub + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    pub fn new() -> Self {
+        BreakpointSet::default()
+    }
+
+    /// Adds a breakpoint, returning its id.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint<T>) -> usize {
+        self.add(Point::Breakpoint(breakpoint))
+    }
+
+    /// Adds a watchpoint, returning its id.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint<T>) -> usize {
+        self.add(Point::Watchpoint(watchpoint))
+    }
+
+    fn add(&mut self, point: Point<T>) -> usize {
+        let id = self.next_id;
+        self.points.insert(id, point);
+        self.next_id += 1;
+        id
+    }
+
+    /// Removes the breakpoint or watchpoint with the given id, returning it if it existed.
+    pub fn remove(&mut self, id: usize) -> Option<Point<T>> {
+        self.points.remove(&id)
+    }
+
+    /// Returns the breakpoints and watchpoints in the order they were added, with their ids.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Point<T>)> {
+        self.points.iter().map(|(&id, point)| (id, point))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Executes a single instruction, then reports the first watchpoint triggered by it or, if
+    /// there is none, the first breakpoint that holds before the next instruction.
+    pub fn step(&self, computer: &mut Computer<T>) -> Result<Option<Hit<T>>, IntcodeError<T>> {
+        let accesses = accesses(computer)?;
+        let old = accesses.destination.clone().and_then(|address| computer.read(address).ok());
+
+        let writes = match (computer.step_with_activity()?, accesses.destination, old) {
+            (Some(activity), _, _) => activity.writes,
+            (None, Some(address), Some(old)) => vec![(address.clone(), old, computer.read(address)?)],
+            _ => vec![]
+        };
+        if let Some(hit) = self.watchpoint_hit(&accesses.reads, &writes) {
+            return Ok(Some(hit));
+        }
+        if !computer.is_running() {
+            return Ok(None);
+        }
+        self.breakpoint_hit(computer)
+    }
+
+    /// Undoes a single recorded instruction, then reports the first watchpoint that the
+    /// instruction triggered or, if there is none, the first breakpoint that holds now that the
+    /// computer is back before the instruction. Returns `None` if there is no history to undo.
+    pub fn step_back(&self, computer: &mut Computer<T>) -> Result<Option<Hit<T>>, IntcodeError<T>> {
+        let change = match computer.step_back() {
+            Some(change) => change,
+            None => return Ok(None)
+        };
+        match self.watchpoint_hit(&accesses(computer)?.reads, &change.writes) {
+            Some(hit) => Ok(Some(hit)),
+            None => self.breakpoint_hit(computer)
+        }
+    }
+
+    /// Runs the computer until a breakpoint or watchpoint is hit, returning `None` if it halts
+    /// first. Breakpoints at the starting location are not checked, so calling this again after a
+    /// breakpoint is hit carries on from there.
+    pub fn run(&self, computer: &mut Computer<T>) -> Result<Option<Hit<T>>, IntcodeError<T>> {
+        while computer.is_running() {
+            if let Some(hit) = self.step(computer)? {
+                return Ok(Some(hit));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs a recording computer backwards until a breakpoint or watchpoint is hit, returning
+    /// `None` if the start of the history is reached first. When a watchpoint is hit the computer
+    /// is left just before the instruction that triggered it.
+    pub fn run_back(&self, computer: &mut Computer<T>) -> Result<Option<Hit<T>>, IntcodeError<T>> {
+        while !computer.history().is_empty() {
+            if let Some(hit) = self.step_back(computer)? {
+                return Ok(Some(hit));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the first watchpoint triggered by an instruction that read the given (address, value)
+    /// pairs and made the given (address, old, new) write.
+    fn watchpoint_hit(&self, reads: &[(T, T)], writes: &[(T, T, T)]) -> Option<Hit<T>> {
+        for (&id, point) in &self.points {
+            let watchpoint = match point {
+                Point::Watchpoint(w) => w,
+                Point::Breakpoint(_) => continue
+            };
+            if let Watch::Read | Watch::Access = watchpoint.watch {
+                if let Some((address, value)) = reads.iter().find(|(a, _)| watchpoint.covers(a)) {
+                    return Some(Hit::Read { id, address: address.clone(), value: value.clone() });
+                }
+            }
+            for (address, old, new) in writes.iter().filter(|(a, _, _)| watchpoint.covers(a)) {
+                let hit = match &watchpoint.watch {
+                    Watch::Write | Watch::Access => true,
+                    Watch::Value(comparison, value) => comparison.test(new, value),
+                    Watch::Read => false
+                };
+                if hit {
+                    return Some(Hit::Write { id, address: address.clone(), old: old.clone(), new: new.clone() });
+                }
+            }
+        }
+        None
+    }
+
+    fn breakpoint_hit(&self, computer: &Computer<T>) -> Result<Option<Hit<T>>, IntcodeError<T>> {
+        for (&id, point) in &self.points {
+            if let Point::Breakpoint(Breakpoint { address, condition }) = point {
+                if address.as_ref().is_none_or(|a| *a == computer.loc())
+                    && (address.is_some() || condition.is_some())
+                    && condition.as_ref().map_or(Ok(true), |c| c.is_true(computer))? {
+                    return Ok(Some(Hit::Breakpoint { id, address: computer.loc() }));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The memory an instruction accesses.
+struct Accesses<T> {
+    /// The (address, value) pairs of the parameters it reads.
+    reads: Vec<(T, T)>,
+    /// The address it writes to.
+    destination: Option<T>,
+}
+
+/// Finds the memory the current instruction will access.
+fn accesses<T>(computer: &Computer<T>) -> Result<Accesses<T>, IntcodeError<T>>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let instruction = computer.current_instruction()?;
+    let destination = instruction.written_argument();
+    let reads = instruction.arguments().into_iter()
+        .filter(|a| destination.is_none_or(|d| !std::ptr::eq(*a, d)))
+        .filter_map(|a| a.address())
+        .filter_map(|address| computer.read(address.clone()).ok().map(|value| (address, value)))
+        .collect();
+    Ok(Accesses { reads, destination: destination.and_then(|a| a.address()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::extension::{Effect, Opcode};
+
+    fn breakpoint(address: Option<i64>, condition: Option<&str>) -> Breakpoint<i64> {
+        Breakpoint { address, condition: condition.map(|c| c.parse().unwrap()) }
+    }
+
+    fn watchpoint(start: i64, end: i64, watch: Watch<i64>) -> Watchpoint<i64> {
+        Watchpoint { start, end, watch }
+    }
+
+    /// Counts [counter] down from 3, storing each new value in the table after it.
+    fn countdown() -> Computer<i64> {
+        Computer::new(assemble("
+                    arb #table
+            loop:   add [counter], #-1, [counter]
+                    add [counter], #0, rb+0
+                    arb #1
+                    jt [counter], #loop
+                    hlt
+            counter: .data 3
+            table:  .zero 3").unwrap(), None, None)
+    }
+
+    #[test]
+    fn test_breakpoint_at_address() {
+        let mut computer = countdown();
+        let mut set = BreakpointSet::new();
+        let id = set.add_breakpoint(breakpoint(Some(2), None));
+        assert_eq!(Ok(Some(Hit::Breakpoint { id, address: 2 })), set.run(&mut computer));
+        assert_eq!(Ok(3), computer.read(16));
+        assert_eq!(Ok(Some(Hit::Breakpoint { id, address: 2 })), set.run(&mut computer));
+        assert_eq!(Ok(2), computer.read(16));
+        assert_eq!(Ok(Some(Hit::Breakpoint { id, address: 2 })), set.run(&mut computer));
+        assert_eq!(Ok(1), computer.read(16));
+        assert_eq!(Ok(None), set.run(&mut computer));
+        assert!(!computer.is_running());
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut computer = countdown();
+        let mut set = BreakpointSet::new();
+        let id = set.add_breakpoint(breakpoint(Some(2), Some("[16] == 1")));
+        assert_eq!(Ok(Some(Hit::Breakpoint { id, address: 2 })), set.run(&mut computer));
+        assert_eq!(Ok(1), computer.read(16));
+
+        let mut computer = countdown();
+        let mut set = BreakpointSet::new();
+        let id = set.add_breakpoint(breakpoint(None, Some("rb == 19")));
+        assert_eq!(Ok(Some(Hit::Breakpoint { id, address: 12 })), set.run(&mut computer));
+    }
+
+    #[test]
+    fn test_write_watchpoint_over_range() {
+        let mut computer = countdown();
+        let mut set = BreakpointSet::new();
+        let id = set.add_watchpoint(watchpoint(18, 19, Watch::Write));
+        assert_eq!(Ok(Some(Hit::Write { id, address: 18, old: 0, new: 1 })), set.run(&mut computer));
+        assert_eq!(Ok(Some(Hit::Write { id, address: 19, old: 0, new: 0 })), set.run(&mut computer));
+        assert_eq!(Ok(None), set.run(&mut computer));
+    }
+
+    #[test]
+    fn test_value_watchpoint() {
+        let mut computer = countdown();
+        let mut set = BreakpointSet::new();
+        let id = set.add_watchpoint(watchpoint(16, 16, Watch::Value(Comparison::Less, 2)));
+        assert_eq!(Ok(Some(Hit::Write { id, address: 16, old: 2, new: 1 })), set.run(&mut computer));
+        assert_eq!(6, computer.loc());
+    }
+
+    #[test]
+    fn test_read_watchpoint() {
+        let mut computer = countdown();
+        let mut set = BreakpointSet::new();
+        let id = set.add_watchpoint(watchpoint(16, 16, Watch::Read));
+        assert_eq!(Ok(Some(Hit::Read { id, address: 16, value: 3 })), set.run(&mut computer));
+        assert_eq!(Ok(2), computer.read(16));
+        assert_eq!(Ok(Some(Hit::Read { id, address: 16, value: 2 })), set.run(&mut computer));
+        assert_eq!(Ok(2), computer.read(16));
+        assert_eq!(10, computer.loc());
+    }
+
+    #[test]
+    fn test_watch_self_modifying_code() {
+        // Overwrites its own output instruction's parameter before running it.
+        let mut computer = Computer::new(vec![1101, 7, 0, 5, 104, 0, 99], None, None);
+        let mut set = BreakpointSet::new();
+        let id = set.add_watchpoint(watchpoint(4, 6, Watch::Write));
+        assert_eq!(Ok(Some(Hit::Write { id, address: 5, old: 0, new: 7 })), set.run(&mut computer));
+        assert_eq!(Ok(None), set.run(&mut computer));
+        assert_eq!(vec![7], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_watch_extension_writes() {
+        // Opcode 20 stores 5 at address 6 without naming it in a parameter.
+        let mut computer = Computer::new(vec![20, 104, 1, 99, 0, 0, 0], None, None);
+        computer.register_opcode(20, Opcode::new("poke", &[], |context| context.write(&6, 5).map(|_| Effect::Continue))).unwrap();
+        computer.start_recording();
+        let mut set = BreakpointSet::new();
+        let id = set.add_watchpoint(watchpoint(6, 6, Watch::Write));
+        assert_eq!(Ok(Some(Hit::Write { id, address: 6, old: 0, new: 5 })), set.run(&mut computer));
+        assert_eq!(1, computer.loc());
+        assert_eq!(Ok(Some(Hit::Write { id, address: 6, old: 0, new: 5 })), set.step_back(&mut computer));
+        assert_eq!(0, computer.loc());
+    }
+
+    #[test]
+    fn test_run_back_to_write() {
+        let mut computer = countdown();
+        computer.start_recording();
+        let mut set = BreakpointSet::new();
+        assert_eq!(Ok(None), set.run(&mut computer));
+        assert_eq!(Ok(1), computer.read(18));
+
+        let id = set.add_watchpoint(watchpoint(18, 18, Watch::Write));
+        assert_eq!(Ok(Some(Hit::Write { id, address: 18, old: 0, new: 1 })), set.run_back(&mut computer));
+        assert_eq!(6, computer.loc());
+        assert_eq!(Ok(0), computer.read(18));
+        assert_eq!(Ok(1), computer.read(16));
+        assert_eq!(Ok(None), set.run_back(&mut computer));
+        assert_eq!(0, computer.loc());
+
+        assert_eq!(Ok(Some(Hit::Write { id, address: 18, old: 0, new: 1 })), set.run(&mut computer));
+        assert_eq!(10, computer.loc());
+    }
+
+    #[test]
+    fn test_step_back_to_breakpoint() {
+        let mut computer = countdown();
+        computer.start_recording();
+        let mut set = BreakpointSet::new();
+        set.run(&mut computer).unwrap();
+        let id = set.add_breakpoint(breakpoint(Some(12), Some("[16] == 2")));
+        assert_eq!(Ok(Some(Hit::Breakpoint { id, address: 12 })), set.run_back(&mut computer));
+        assert_eq!(Ok(2), computer.read(16));
+        assert_eq!(18, computer.relative_base());
+    }
+
+    #[test]
+    fn test_remove_and_display() {
+        let mut set = BreakpointSet::new();
+        let first = set.add_breakpoint(breakpoint(Some(4), Some("rb > 1")));
+        let second = set.add_watchpoint(watchpoint(10, 12, Watch::Value(Comparison::Equal, 5)));
+        set.add_watchpoint(watchpoint(3, 3, Watch::Access));
+        assert_eq!(Some(Point::Breakpoint(breakpoint(Some(4), Some("rb > 1")))), set.remove(first));
+        assert_eq!(None, set.remove(first));
+        let listing: Vec<String> = set.iter().map(|(id, p)| format!("{}: {}", id, p)).collect();
+        assert_eq!(vec![format!("{}: watch [10..12] == 5", second), String::from("3: watch access [3]")], listing);
+    }
+}
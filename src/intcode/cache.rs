@@ -0,0 +1,88 @@
+use super::Instruction;
+
+/// Instructions are only cached at addresses below this limit. Programs keep their code near the
+/// start of memory, and this stops a stray jump to a huge address allocating a huge cache.
+const CACHE_LIMIT: usize = 1 << 20;
+
+/// The number of words in the longest built-in instruction.
+const MAX_LENGTH: usize = 4;
+
+/// Instructions that a `Computer` has already decoded, keyed by the address they start at, so
+/// that code run many times is only decoded once.
+///
+/// An entry holds the instruction as decoded against the relative base of the time, so the
+/// computer must update the relative base of an instruction taken from the cache. Entries are
+/// dropped when memory under any of their words is written.
+pub(crate) struct DecodeCache<T> {
+    entries: Vec<Option<(Instruction<T>, usize)>>,
+    /// The number of words in the longest instruction that may be cached.
+    max_length: usize,
+}
+
+impl<T> DecodeCache<T> {
+    pub(crate) fn new() -> Self {
+        DecodeCache { entries: vec![], max_length: MAX_LENGTH }
+    }
+
+    pub(crate) fn get(&self, address: usize) -> Option<&Instruction<T>> {
+        self.entries.get(address)?.as_ref().map(|(instruction, _)| instruction)
+    }
+
+    /// Caches an instruction of the given length in words.
+    pub(crate) fn insert(&mut self, address: usize, instruction: Instruction<T>, length: usize) {
+        if address >= CACHE_LIMIT {
+            return;
+        }
+        if address >= self.entries.len() {
+            self.entries.resize_with(address + 1, || None);
+        }
+        self.entries[address] = Some((instruction, length));
+    }
+
+    /// Allows for instructions of the given length in words, such as those of an extension
+    /// opcode.
+    pub(crate) fn allow_length(&mut self, length: usize) {
+        self.max_length = self.max_length.max(length);
+    }
+
+    /// Drops any instruction with a word at the given address.
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        for start in address.saturating_sub(self.max_length - 1)..=address {
+            if matches!(self.entries.get(start), Some(Some((_, length))) if start + length > address) {
+                self.entries[start] = None;
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate_covers_whole_instruction() {
+        let mut cache: DecodeCache<i64> = DecodeCache::new();
+        cache.insert(2, Instruction::Stop, 1);
+        cache.insert(3, Instruction::Stop, 4);
+        cache.invalidate(2);
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_some());
+        cache.invalidate(7);
+        assert!(cache.get(3).is_some());
+        cache.invalidate(6);
+        assert!(cache.get(3).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_beyond_cache() {
+        let mut cache: DecodeCache<i64> = DecodeCache::new();
+        cache.invalidate(0);
+        cache.invalidate(100);
+        cache.insert(CACHE_LIMIT, Instruction::Stop, 1);
+        assert!(cache.get(CACHE_LIMIT).is_none());
+    }
+}
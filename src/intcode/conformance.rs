@@ -0,0 +1,196 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, Limits};
+use super::memory::MemoryKind;
+use crate::util;
+
+/// The number of steps a conformance case may take before it is considered stuck.
+const MAX_STEPS: u64 = 1_000_000;
+
+/// A program run with some input, and what it is expected to do.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Case {
+    /// The fixture file and line the case starts on.
+    pub name: String,
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+    /// The values the program must output, if given.
+    pub output: Option<Vec<i64>>,
+    /// The values memory must hold from address zero when the program halts, if given.
+    pub memory: Option<Vec<i64>>,
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (input {})", self.name, self.input.iter().join(","))
+    }
+}
+
+impl Case {
+    /// Runs the case on a computer with the given integer type and memory, checking that it halts
+    /// with the expected output and memory.
+    pub fn run<T>(&self, memory: MemoryKind) -> Result<(), String>
+    where
+        T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+    {
+        let widen = |values: &[i64]| values.iter()
+            .map(|&v| T::from_i64(v).ok_or_else(|| format!("{}: value {} is out of range.", self, v)))
+            .collect::<Result<Vec<T>, String>>();
+        let mut computer = Computer::with_memory(memory.load(widen(&self.program)?), Some(ComputerInput::Queue(widen(&self.input)?.into())), None);
+        computer.set_limits(Limits { max_steps: Some(MAX_STEPS), ..Limits::default() });
+        computer.run().map_err(|e| format!("{}: {}", self, e))?;
+
+        if let Some(expected) = &self.output {
+            let output = computer.output();
+            if output.iter().ne(widen(expected)?.iter()) {
+                return Err(format!("{}: expected output {} but got {}.", self, expected.iter().join(","), output.iter().join(",")));
+            }
+        }
+        if let Some(expected) = &self.memory {
+            let actual = (0..expected.len())
+                .map(|address| T::from_usize(address)
+                    .ok_or_else(|| format!("{}: address {} is out of range.", self, address))
+                    .and_then(|address| computer.read(address).map_err(|e| format!("{}: {}", self, e))))
+                .collect::<Result<Vec<T>, _>>()?;
+            if actual != widen(expected)? {
+                return Err(format!("{}: expected memory {} but got {}.", self, expected.iter().join(","), actual.iter().join(",")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the cases from every `.txt` fixture file in a directory, in file name order.
+///
+/// A fixture file holds cases separated by blank lines. Each case is a set of `key: value` lines,
+/// where the values are comma-separated integers:
+///
+/// * `program` gives the program itself, or `program-file` names a file holding it, relative to
+///   the fixture. A case without either runs the program of the case before it.
+/// * `input` gives the input values, if the program takes any.
+/// * `output` and `memory` give the expected output, and the expected memory from address zero
+///   when the program halts. A case must check at least one of them.
+///
+/// Lines starting with `#` are comments.
+pub fn load<P: AsRef<Path>>(directory: P) -> io::Result<Vec<Case>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "txt"));
+    paths.sort();
+
+    let mut cases = vec![];
+    for path in paths {
+        cases.extend(parse(&path, &fs::read_to_string(&path)?)?);
+    }
+    Ok(cases)
+}
+
+/// Parses the cases in one fixture file.
+fn parse(path: &Path, text: &str) -> io::Result<Vec<Case>> {
+    let invalid = |line: usize, message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line, message));
+    let values = |line: usize, text: &str| -> io::Result<Vec<i64>> {
+        text.split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse().map_err(|_| invalid(line, format!("Invalid value '{}'.", value))))
+            .collect()
+    };
+
+    let mut cases = vec![];
+    let mut program: Option<Vec<i64>> = None;
+    // The lines of the case being read, with their line numbers.
+    let mut lines: Vec<(usize, &str)> = vec![];
+    for (number, line) in text.lines().map(str::trim).enumerate().chain(std::iter::once((usize::MAX, ""))) {
+        if line.starts_with('#') {
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push((number + 1, line));
+            continue;
+        }
+        let start = match lines.first() {
+            Some(&(start, _)) => start,
+            None => continue
+        };
+
+        let mut case = Case { name: format!("{}:{}", path.display(), start), program: vec![], input: vec![], output: None, memory: None };
+        for (number, line) in lines.drain(..) {
+            let (key, value) = line.split_at(line.find(':').ok_or_else(|| invalid(number, String::from("Expected key: value.")))?);
+            let value = &value[1..];
+            match key.trim() {
+                "program" => program = Some(values(number, value)?),
+                "program-file" => {
+                    let file = path.parent().unwrap_or_else(|| Path::new("")).join(value.trim());
+                    let read = File::open(&file).and_then(util::read_comma_separated_integers);
+                    program = Some(read.map_err(|e| invalid(number, format!("Can't read '{}': {}", file.display(), e)))?);
+                },
+                "input" => case.input = values(number, value)?,
+                "output" => case.output = Some(values(number, value)?),
+                "memory" => case.memory = Some(values(number, value)?),
+                key => return Err(invalid(number, format!("Unknown key '{}'.", key)))
+            }
+        }
+        case.program = program.clone().ok_or_else(|| invalid(start, String::from("The case has no program.")))?;
+        if case.output.is_none() && case.memory.is_none() {
+            return Err(invalid(start, String::from("The case checks neither output nor memory.")));
+        }
+        cases.push(case);
+    }
+    Ok(cases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::BigInt;
+
+    #[test]
+    fn test_conformance() {
+        let cases = load("inputs/conformance").unwrap();
+        assert!(cases.len() >= 36);
+        let failures: Vec<String> = cases.iter()
+            .flat_map(|case| vec![case.run::<i64>(MemoryKind::Dense), case.run::<i64>(MemoryKind::Sparse), case.run::<BigInt>(MemoryKind::Dense)])
+            .filter_map(Result::err)
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# Echo\nprogram: 3,0,4,0,99\ninput: 5\noutput: 5\n\ninput: -1\noutput: -1\nmemory: -1,0,4\n";
+        let cases = parse(Path::new("echo.txt"), text).unwrap();
+        assert_eq!(2, cases.len());
+        assert_eq!("echo.txt:2", cases[0].name);
+        assert_eq!(vec![3, 0, 4, 0, 99], cases[1].program);
+        assert_eq!(Some(vec![-1, 0, 4]), cases[1].memory);
+        assert!(cases[1].run::<i64>(MemoryKind::Dense).is_ok());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| parse(Path::new("bad.txt"), text).unwrap_err().to_string();
+        assert_eq!("bad.txt:1: The case has no program.", error("output: 1"));
+        assert_eq!("bad.txt:1: The case checks neither output nor memory.", error("program: 99"));
+        assert_eq!("bad.txt:2: Unknown key 'outptu'.", error("program: 99\noutptu: 1"));
+        assert_eq!("bad.txt:2: Invalid value 'x'.", error("program: 99\noutput: x"));
+        assert!(error("output: 1\nprogram-file: missing.txt").starts_with("bad.txt:2: Can't read 'missing.txt': "));
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let case = Case { name: String::from("t"), program: vec![104, 1, 99], input: vec![], output: Some(vec![2]), memory: None };
+        assert_eq!("t (input ): expected output 2 but got 1.", case.run::<i64>(MemoryKind::Dense).unwrap_err());
+    }
+
+    #[test]
+    fn test_values_out_of_range() {
+        let case = Case { name: String::from("t"), program: vec![104, 1 << 40, 99], input: vec![], output: Some(vec![1 << 40]), memory: None };
+        assert_eq!("t (input ): value 1099511627776 is out of range.", case.run::<i32>(MemoryKind::Dense).unwrap_err());
+    }
+}
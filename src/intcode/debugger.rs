@@ -0,0 +1,395 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, CheckedSub, FromPrimitive, ToPrimitive};
+
+use super::Computer;
+use super::breakpoints::{Breakpoint, BreakpointSet, Watch, Watchpoint};
+use super::expression::Expression;
+
+const HELP: &str = "Commands:
+  step [n]            Execute n instructions (default 1).
+  continue            Run until a breakpoint is reached or the program halts.
+  record [on|off]     Start or stop recording execution, so that it can be run backwards.
+  back [n]            Undo n recorded instructions (default 1).
+  rcontinue           Run backwards until a breakpoint or watchpoint is hit, or the start of the
+                      recording is reached.
+  break <addr>        Set a breakpoint at an address.
+  break [addr] if <condition>
+                      Set a breakpoint that stops when the condition is true, optionally only
+                      at an address. Conditions are expressions over loc, rb and memory, such
+                      as `[rb+1] > 5 && loc != 10`.
+  watch <range> [read|write|access]
+                      Stop after an instruction accesses memory in a range, given as `addr` or
+                      `start..end`. Watches writes by default.
+  watch <range> <op> <value>
+                      Stop after an instruction writes a value to the range that compares
+                      with the value using ==, !=, <, <=, > or >=.
+  delete <id>         Remove a breakpoint or watchpoint.
+  breakpoints         List breakpoints and watchpoints.
+  show                Show the registers and the current instruction.
+  mem <addr> [n]      Show n memory values (default 1), starting at an address.
+  set <addr> <value>  Write a value to memory.
+  rb [value]          Show or set the relative base.
+  input <values...>   Add values to the input queue.
+  output              Show the output produced so far.
+  help                Show this message.
+  quit                Exit the debugger.";
+
+/// A command understood by the debugger.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Command<T> {
+    Step(usize),
+    Continue,
+    Record(bool),
+    StepBack(usize),
+    ReverseContinue,
+    Break(T),
+    BreakIf(Option<T>, Expression<T>),
+    Watch(Watchpoint<T>),
+    Delete(usize),
+    Breakpoints,
+    Show,
+    Memory(T, usize),
+    Set(T, T),
+    RelativeBase(Option<T>),
+    Input(Vec<T>),
+    Output,
+    Help,
+    Quit,
+}
+
+impl<T: FromStr> Command<T> {
+    /// Parses a command line. Each command may also be given as its first letter, except for
+    /// `rb`, `output`, `record`, `breakpoints` (`bl`) and `rcontinue` (`rc`).
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let arguments: Vec<&str> = words.collect();
+        let value = |index: usize| -> Result<T, String> {
+            let text = arguments.get(index).ok_or_else(|| format!("Missing argument for '{}'.", name))?;
+            text.parse().map_err(|_| format!("Invalid value '{}'.", text))
+        };
+        let rest = |index: usize| arguments[index.min(arguments.len())..].join(" ");
+        let count = |index: usize| -> Result<usize, String> {
+            match arguments.get(index) {
+                Some(text) => text.parse().map_err(|_| format!("Invalid count '{}'.", text)),
+                None => Ok(1)
+            }
+        };
+
+        match name {
+            "s" | "step" => Ok(Command::Step(count(0)?)),
+            "c" | "continue" => Ok(Command::Continue),
+            "record" => match arguments.first() {
+                None | Some(&"on") => Ok(Command::Record(true)),
+                Some(&"off") => Ok(Command::Record(false)),
+                Some(text) => Err(format!("Expected 'on' or 'off' but found '{}'.", text))
+            },
+            "back" => Ok(Command::StepBack(count(0)?)),
+            "rc" | "rcontinue" => Ok(Command::ReverseContinue),
+            "b" | "break" => match arguments.iter().position(|&a| a == "if") {
+                Some(0) => Ok(Command::BreakIf(None, rest(1).parse()?)),
+                Some(1) => Ok(Command::BreakIf(Some(value(0)?), rest(2).parse()?)),
+                Some(_) => Err(String::from("Expected 'break <addr> if <condition>'.")),
+                None => Ok(Command::Break(value(0)?))
+            },
+            "w" | "watch" => {
+                let range = arguments.first().ok_or_else(|| format!("Missing argument for '{}'.", name))?;
+                let parse = |text: &str| text.parse().map_err(|_| format!("Invalid value '{}'.", text));
+                let (start, end) = match range.find("..") {
+                    Some(i) => (parse(&range[..i])?, parse(&range[i + 2..])?),
+                    None => (parse(range)?, parse(range)?)
+                };
+                let watch = match arguments.get(1) {
+                    None | Some(&"write") => Watch::Write,
+                    Some(&"read") => Watch::Read,
+                    Some(&"access") => Watch::Access,
+                    Some(comparison) => Watch::Value(comparison.parse()?, value(2)?)
+                };
+                Ok(Command::Watch(Watchpoint { start, end, watch }))
+            },
+            "d" | "delete" => Ok(Command::Delete(count(0)?)),
+            "bl" | "breakpoints" => Ok(Command::Breakpoints),
+            "" | "show" => Ok(Command::Show),
+            "m" | "mem" => Ok(Command::Memory(value(0)?, count(1)?)),
+            "set" => Ok(Command::Set(value(0)?, value(1)?)),
+            "rb" if arguments.is_empty() => Ok(Command::RelativeBase(None)),
+            "rb" => Ok(Command::RelativeBase(Some(value(0)?))),
+            "i" | "input" => Ok(Command::Input((0..arguments.len()).map(value).collect::<Result<_, _>>()?)),
+            "output" => Ok(Command::Output),
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            _ => Err(format!("Unknown command '{}'. Type 'help' for a list of commands.", name))
+        }
+    }
+}
+
+/// An interactive debugger wrapping a `Computer`.
+pub struct Debugger<T: Signed + Integer> {
+    computer: Computer<T>,
+    breakpoints: BreakpointSet<T>,
+}
+
+impl<T> Debugger<T>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedSub + CheckedMul + FromPrimitive + ToPrimitive + FromStr + fmt::Display + Send + 'static,
+{
+    pub fn new(computer: Computer<T>) -> Self {
+        Debugger { computer, breakpoints: BreakpointSet::new() }
+    }
+
+    pub fn computer(&self) -> &Computer<T> {
+        &self.computer
+    }
+
+    pub fn breakpoints(&self) -> &BreakpointSet<T> {
+        &self.breakpoints
+    }
+
+    /// Executes a single command, returning the text to show to the user.
+    pub fn execute(&mut self, command: &Command<T>) -> Result<String, String> {
+        match command {
+            Command::Step(count) => {
+                for _ in 0..*count {
+                    if !self.computer.is_running() {
+                        break;
+                    }
+                    if let Some(hit) = self.breakpoints.step(&mut self.computer).map_err(|e| e.to_string())? {
+                        return Ok(format!("{}\n{}", hit, self.location()));
+                    }
+                }
+                Ok(self.location())
+            },
+            Command::Continue => match self.breakpoints.run(&mut self.computer).map_err(|e| e.to_string())? {
+                Some(hit) => Ok(format!("{}\n{}", hit, self.location())),
+                None => Ok(self.location())
+            },
+            Command::Record(true) => {
+                self.computer.start_recording();
+                Ok(String::from("Recording."))
+            },
+            Command::Record(false) => {
+                self.computer.stop_recording();
+                Ok(String::from("Recording stopped."))
+            },
+            Command::StepBack(_) | Command::ReverseContinue if !self.computer.is_recording() =>
+                Err(String::from("Not recording: use 'record' to enable running backwards.")),
+            Command::StepBack(count) => {
+                for _ in 0..*count {
+                    if self.computer.history().is_empty() {
+                        return Ok(format!("Reached the start of the recording.\n{}", self.location()));
+                    }
+                    if let Some(hit) = self.breakpoints.step_back(&mut self.computer).map_err(|e| e.to_string())? {
+                        return Ok(format!("{}\n{}", hit, self.location()));
+                    }
+                }
+                Ok(self.location())
+            },
+            Command::ReverseContinue => match self.breakpoints.run_back(&mut self.computer).map_err(|e| e.to_string())? {
+                Some(hit) => Ok(format!("{}\n{}", hit, self.location())),
+                None => Ok(format!("Reached the start of the recording.\n{}", self.location()))
+            },
+            Command::Break(address) => {
+                let id = self.breakpoints.add_breakpoint(Breakpoint { address: Some(address.clone()), condition: None });
+                Ok(format!("Breakpoint {} set at {}.", id, address))
+            },
+            Command::BreakIf(address, condition) => {
+                let id = self.breakpoints.add_breakpoint(Breakpoint { address: address.clone(), condition: Some(condition.clone()) });
+                Ok(format!("Breakpoint {} set.", id))
+            },
+            Command::Watch(watchpoint) => {
+                let id = self.breakpoints.add_watchpoint(watchpoint.clone());
+                Ok(format!("Watchpoint {} set.", id))
+            },
+            Command::Delete(id) => match self.breakpoints.remove(*id) {
+                Some(_) => Ok(format!("Deleted {}.", id)),
+                None => Err(format!("No breakpoint or watchpoint {}.", id))
+            },
+            Command::Breakpoints if self.breakpoints.is_empty() => Ok(String::from("No breakpoints.")),
+            Command::Breakpoints => Ok(self.breakpoints.iter().map(|(id, point)| format!("{}: {}", id, point)).join("\n")),
+            Command::Show => Ok(self.location()),
+            Command::Memory(start, count) => {
+                let values = (0..*count)
+                    .map(|offset| T::from_usize(offset).and_then(|offset| start.checked_add(&offset))
+                        .ok_or_else(|| format!("Address {} + {} is too large.", start, offset)))
+                    .map(|address| address.and_then(|address| self.computer.read(address.clone())
+                        .map(|value| format!("[{}] = {}", address, value))
+                        .map_err(|e| e.to_string())))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(values.join("\n"))
+            },
+            Command::Set(address, value) => {
+                self.computer.write(address.clone(), value.clone()).map_err(|e| e.to_string())?;
+                Ok(format!("[{}] = {}", address, value))
+            },
+            Command::RelativeBase(value) => {
+                if let Some(v) = value {
+                    self.computer.set_relative_base(v.clone());
+                }
+                Ok(format!("rb = {}", self.computer.relative_base()))
+            },
+            Command::Input(values) => {
+                for value in values {
+                    self.computer.push_input(value.clone())?;
+                }
+                Ok(format!("Queued {} input value(s).", values.len()))
+            },
+            Command::Output => Ok(format!("Output: {}", self.computer.output().iter().join(", "))),
+            Command::Help => Ok(String::from(HELP)),
+            Command::Quit => Ok(String::new())
+        }
+    }
+
+    /// Runs an interactive session, reading commands from `input` and writing responses to
+    /// `output` until `quit` is entered or the input is exhausted.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        write!(output, "(icdb) ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let response = match Command::parse(line?.trim()) {
+                Ok(Command::Quit) => break,
+                Ok(command) => self.execute(&command),
+                Err(e) => Err(e)
+            };
+            match response {
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(e) => writeln!(output, "Error: {}", e)?
+            };
+            write!(output, "(icdb) ")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        let registers = format!("loc = {}, rb = {}", self.computer.loc(), self.computer.relative_base());
+        if !self.computer.is_running() {
+            return format!("{} (halted)", registers);
+        }
+        match self.computer.current_instruction() {
+            Ok(instruction) => format!("{}\n=> {}", registers, instruction),
+            Err(e) => format!("{}\n=> {}", registers, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::expression::Comparison;
+
+    fn debugger(program: Vec<i64>) -> Debugger<i64> {
+        Debugger::new(Computer::new(program, None, None))
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Ok(Command::Step(1)), Command::<i64>::parse("s"));
+        assert_eq!(Ok(Command::Step(5)), Command::<i64>::parse("step 5"));
+        assert_eq!(Ok(Command::Memory(10, 3)), Command::<i64>::parse("mem 10 3"));
+        assert_eq!(Ok(Command::Input(vec![1, -2])), Command::<i64>::parse("input 1 -2"));
+        assert_eq!(Ok(Command::RelativeBase(None)), Command::<i64>::parse("rb"));
+        assert!(Command::<i64>::parse("set 4").is_err());
+        assert!(Command::<i64>::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_step_shows_instruction() {
+        let mut debugger = debugger(vec![1101, 2, 3, 5, 99, 0]);
+        assert_eq!("loc = 4, rb = 0\n=> hlt", debugger.execute(&Command::Step(1)).unwrap());
+        assert_eq!(Ok(5), debugger.computer().read(5));
+    }
+
+    #[test]
+    fn test_continue_stops_at_breakpoint() {
+        let mut debugger = debugger(vec![1101, 2, 3, 9, 104, 7, 104, 8, 99, 0]);
+        debugger.execute(&Command::Break(6)).unwrap();
+        let response = debugger.execute(&Command::Continue).unwrap();
+        assert!(response.starts_with("Breakpoint at 6."));
+        assert_eq!(vec![7], Vec::from(debugger.computer().output()));
+        debugger.execute(&Command::Continue).unwrap();
+        assert_eq!(vec![7, 8], Vec::from(debugger.computer().output()));
+        assert!(!debugger.computer().is_running());
+    }
+
+    #[test]
+    fn test_parse_breakpoints_and_watchpoints() {
+        assert_eq!(Ok(Command::BreakIf(Some(4), "[rb] == 2".parse().unwrap())), Command::<i64>::parse("break 4 if [rb] == 2"));
+        assert_eq!(Ok(Command::BreakIf(None, "loc > 10".parse().unwrap())), Command::<i64>::parse("b if loc > 10"));
+        assert_eq!(Ok(Command::Watch(Watchpoint { start: 3, end: 3, watch: Watch::Write })), Command::<i64>::parse("watch 3"));
+        assert_eq!(Ok(Command::Watch(Watchpoint { start: 3, end: 8, watch: Watch::Read })), Command::<i64>::parse("watch 3..8 read"));
+        assert_eq!(Ok(Command::Watch(Watchpoint { start: 0, end: 1, watch: Watch::Value(Comparison::Greater, -2) })),
+                   Command::<i64>::parse("w 0..1 > -2"));
+        assert_eq!(Ok(Command::Delete(2)), Command::<i64>::parse("delete 2"));
+        assert!(Command::<i64>::parse("break 4 if").is_err());
+        assert!(Command::<i64>::parse("watch 3 ~ 1").is_err());
+    }
+
+    #[test]
+    fn test_watchpoints_in_session() {
+        let mut debugger = debugger(vec![1101, 2, 3, 11, 104, 7, 1102, 2, 3, 11, 99, 0]);
+        assert_eq!("Watchpoint 1 set.", debugger.execute(&Command::parse("watch 11 == 6").unwrap()).unwrap());
+        assert_eq!("Breakpoint 2 set.", debugger.execute(&Command::parse("break if [11] == 5").unwrap()).unwrap());
+        assert_eq!("1: watch [11] == 6\n2: break if ([11] == 5)", debugger.execute(&Command::Breakpoints).unwrap());
+        assert!(debugger.execute(&Command::Continue).unwrap().starts_with("Breakpoint at 4."));
+        debugger.execute(&Command::Delete(2)).unwrap();
+        assert!(debugger.execute(&Command::Delete(2)).is_err());
+        let response = debugger.execute(&Command::Step(5)).unwrap();
+        assert!(response.starts_with("Watchpoint 1: [11] changed from 5 to 6.\nloc = 10"));
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let mut debugger = debugger(vec![1101, 2, 3, 11, 104, 7, 1102, 2, 3, 11, 99, 0]);
+        assert!(debugger.execute(&Command::StepBack(1)).is_err());
+        assert_eq!(Ok(Command::Record(true)), Command::<i64>::parse("record"));
+        debugger.execute(&Command::Record(true)).unwrap();
+        debugger.execute(&Command::Continue).unwrap();
+        assert!(!debugger.computer().is_running());
+
+        debugger.execute(&Command::parse("watch 11").unwrap()).unwrap();
+        let response = debugger.execute(&Command::parse("rc").unwrap()).unwrap();
+        assert_eq!("Watchpoint 1: [11] changed from 5 to 6.\nloc = 6, rb = 0\n=> mul #2, #3, [11]", response);
+        assert_eq!(vec![7], Vec::from(debugger.computer().output()));
+        debugger.execute(&Command::StepBack(1)).unwrap();
+        assert!(debugger.computer().output().is_empty());
+        assert!(debugger.execute(&Command::StepBack(5)).unwrap().starts_with("Watchpoint 1: [11] changed from 0 to 5."));
+        assert!(debugger.execute(&Command::StepBack(5)).unwrap().starts_with("Reached the start of the recording."));
+        assert_eq!(0, debugger.computer().loc());
+    }
+
+    #[test]
+    fn test_input_and_memory() {
+        let mut debugger = debugger(vec![3, 5, 4, 5, 99, 0]);
+        assert!(debugger.execute(&Command::Step(1)).is_err());
+        debugger.execute(&Command::Input(vec![42])).unwrap();
+        debugger.execute(&Command::Step(1)).unwrap();
+        assert_eq!("[5] = 42", debugger.execute(&Command::Memory(5, 1)).unwrap());
+        debugger.execute(&Command::Set(5, 43)).unwrap();
+        debugger.execute(&Command::Continue).unwrap();
+        assert_eq!(vec![43], Vec::from(debugger.computer().output()));
+    }
+
+    #[test]
+    fn test_memory_near_largest_address() {
+        let mut debugger = debugger(vec![99]);
+        assert_eq!("Address 9223372036854775807 + 1 is too large.", debugger.execute(&Command::Memory(i64::MAX, 2)).unwrap_err());
+    }
+
+    #[test]
+    fn test_interactive_session() {
+        let mut debugger = debugger(vec![109, 3, 204, 1, 99]);
+        let mut transcript = vec![];
+        debugger.run("step\nrb\nrb 1\ncontinue\noutput\nbogus\nquit\n".as_bytes(), &mut transcript).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert!(transcript.contains("=> arb #3"));
+        assert!(transcript.contains("rb = 3"));
+        assert!(transcript.contains("Output: 204"));
+        assert!(transcript.contains("Error: Unknown command 'bogus'."));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{ArgumentKind, Computer, Instruction, convert};
+use super::memory::{Memory, MemoryKind};
+
+/// A decoded entry in a program listing.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Entry<T> {
+    Instruction(Instruction<T>),
+    Data(T),
+}
+
+/// A single line of a program listing: the address it starts at, the raw words it covers and
+/// their decoded meaning.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Line<T> {
+    pub address: usize,
+    pub words: Vec<T>,
+    pub entry: Entry<T>,
+}
+
+impl<T: Integer + Signed + fmt::Display> fmt::Display for Line<T> {
+    /// Formats the line as assembler source, with the address and raw words in a trailing
+    /// comment, so that a listing can be reassembled.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match &self.entry {
+            Entry::Instruction(instruction) => instruction.to_string(),
+            Entry::Data(value) => format!(".data {}", value),
+        };
+        write!(f, "{:<28} ; {:>5}: {}", text, self.address, self.words.iter().join(","))
+    }
+}
+
+/// Disassembles a program image into a listing.
+///
+/// Decoding proceeds linearly from address zero. Any word that does not start a valid
+/// instruction - an unknown opcode, a bad parameter mode, an immediate-mode destination or an
+/// instruction running off the end of the image - is emitted as `.data` and decoding resumes at
+/// the next word.
+pub fn disassemble<T>(program: &[T]) -> Vec<Line<T>>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let memory = MemoryKind::Dense.load(program.to_vec());
+    let mut lines = vec![];
+    let mut address = 0;
+
+    while address < program.len() {
+        let line = match decode(program, address, memory.as_ref()) {
+            Some((instruction, length)) => {
+                Line { address, words: program[address..address + length].to_vec(), entry: Entry::Instruction(instruction) }
+            },
+            None => Line { address, words: vec![program[address].clone()], entry: Entry::Data(program[address].clone()) }
+        };
+        address += line.words.len();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Disassembles a program image into a printable listing, one line per instruction or data word.
+pub fn listing<T>(program: &[T]) -> String
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    disassemble(program).iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Decodes the instruction at `address`, returning it with its length in words, or `None` if the
+/// word there does not start a valid instruction.
+pub(super) fn decode<T>(program: &[T], address: usize, memory: &dyn Memory<T>) -> Option<(Instruction<T>, usize)>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let (code, argument_types) = Computer::read_instruction_code(program[address].clone()).ok()?;
+    let parameters = Instruction::parameter_count(code.clone())?;
+    if argument_types.len() > parameters || address + parameters >= program.len() {
+        return None;
+    }
+
+    let instruction = Instruction::new(code, convert(address), argument_types, memory, T::zero()).ok()?;
+    match instruction.written_argument() {
+        Some(argument) if *argument.kind() == ArgumentKind::Immediate => None,
+        _ => Some((instruction, parameters + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    #[test]
+    fn test_disassemble_modes() {
+        let lines = disassemble(&[21001, 4, 3, -2, 99]);
+        assert_eq!(2, lines.len());
+        assert_eq!(vec![21001, 4, 3, -2], lines[0].words);
+        assert_eq!("add [4], #3, rb-2", match &lines[0].entry {
+            Entry::Instruction(i) => i.to_string(),
+            Entry::Data(_) => String::new()
+        });
+        assert_eq!(4, lines[1].address);
+    }
+
+    #[test]
+    fn test_disassemble_invalid_words_as_data() {
+        let lines = disassemble(&[99, 0, 1103, 3, 4, -5, 1]);
+        let entries: Vec<String> = lines.iter().map(|l| match &l.entry {
+            Entry::Instruction(i) => i.to_string(),
+            Entry::Data(x) => format!(".data {}", x)
+        }).collect();
+        assert_eq!(vec!["hlt", ".data 0", ".data 1103", "in [4]", ".data -5", ".data 1"], entries);
+    }
+
+    #[test]
+    fn test_disassemble_line_format() {
+        let lines = disassemble(&[109, 19]);
+        assert_eq!(format!("{:<28} ; {:>5}: 109,19", "arb #19", 0), lines[0].to_string());
+    }
+
+    #[test]
+    fn test_listing_reassembles() {
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8, 2101, 1, -3, 0, 204, 7];
+        let reassembled: Vec<i32> = assemble(&listing(&program)).unwrap();
+        assert_eq!(program, reassembled);
+    }
+}
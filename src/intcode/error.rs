@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+
+/// An error raised while running an Intcode program.
+///
+/// Every variant records the address of the instruction that failed and the raw instruction
+/// value found there.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum IntcodeError<T> {
+    /// The opcode is not one the computer knows how to execute.
+    UnknownOpcode { address: T, instruction: T },
+    /// The parameter mode digits of the instruction are not all 0, 1 or 2.
+    BadParameterMode { address: T, instruction: T },
+    /// The instruction tried to write its result to an immediate-mode parameter.
+    WriteToImmediate { address: T, instruction: T },
+    /// The instruction tried to read or write memory at a negative address.
+    NegativeAddress { address: T, instruction: T, target: T },
+    /// An input instruction found the input queue empty.
+    InputExhausted { address: T, instruction: T },
+    /// An input instruction found that nothing can ever send on the input channel.
+    ChannelClosed { address: T, instruction: T },
+    /// The computer executed its permitted number of steps without halting.
+    StepLimitExceeded { address: T, instruction: T, limit: u64 },
+    /// The instruction grew memory beyond the permitted number of cells.
+    MemoryLimitExceeded { address: T, instruction: T, limit: usize },
+    /// The computer was still running when its deadline passed.
+    DeadlineExceeded { address: T, instruction: T },
+    /// The result of the instruction was too large to store, with overflow checks turned on, or an
+    /// address it uses is too large to represent.
+    Overflow { address: T, instruction: T },
+    /// The handler for an extension opcode reported that the instruction failed.
+    ExtensionFailed { address: T, instruction: T, message: String },
+}
+
+impl<T: Clone> IntcodeError<T> {
+    /// The address of the instruction that failed.
+    pub fn address(&self) -> T {
+        match self {
+            IntcodeError::UnknownOpcode { address, .. } => address.clone(),
+            IntcodeError::BadParameterMode { address, .. } => address.clone(),
+            IntcodeError::WriteToImmediate { address, .. } => address.clone(),
+            IntcodeError::NegativeAddress { address, .. } => address.clone(),
+            IntcodeError::InputExhausted { address, .. } => address.clone(),
+            IntcodeError::ChannelClosed { address, .. } => address.clone(),
+            IntcodeError::StepLimitExceeded { address, .. } => address.clone(),
+            IntcodeError::MemoryLimitExceeded { address, .. } => address.clone(),
+            IntcodeError::DeadlineExceeded { address, .. } => address.clone(),
+            IntcodeError::Overflow { address, .. } => address.clone(),
+            IntcodeError::ExtensionFailed { address, .. } => address.clone(),
+        }
+    }
+
+    /// The raw value of the instruction that failed.
+    pub fn instruction(&self) -> T {
+        match self {
+            IntcodeError::UnknownOpcode { instruction, .. } => instruction.clone(),
+            IntcodeError::BadParameterMode { instruction, .. } => instruction.clone(),
+            IntcodeError::WriteToImmediate { instruction, .. } => instruction.clone(),
+            IntcodeError::NegativeAddress { instruction, .. } => instruction.clone(),
+            IntcodeError::InputExhausted { instruction, .. } => instruction.clone(),
+            IntcodeError::ChannelClosed { instruction, .. } => instruction.clone(),
+            IntcodeError::StepLimitExceeded { instruction, .. } => instruction.clone(),
+            IntcodeError::MemoryLimitExceeded { instruction, .. } => instruction.clone(),
+            IntcodeError::DeadlineExceeded { instruction, .. } => instruction.clone(),
+            IntcodeError::Overflow { instruction, .. } => instruction.clone(),
+            IntcodeError::ExtensionFailed { instruction, .. } => instruction.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for IntcodeError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntcodeError::UnknownOpcode { address, instruction } =>
+                write!(f, "Unknown opcode in instruction {} at address {}.", instruction, address),
+            IntcodeError::BadParameterMode { address, instruction } =>
+                write!(f, "Bad parameter mode in instruction {} at address {}.", instruction, address),
+            IntcodeError::WriteToImmediate { address, instruction } =>
+                write!(f, "Instruction {} at address {} writes to an immediate parameter.", instruction, address),
+            IntcodeError::NegativeAddress { address, instruction, target } =>
+                write!(f, "Instruction {} at address {} accesses negative address {}.", instruction, address, target),
+            IntcodeError::InputExhausted { address, instruction } =>
+                write!(f, "Failed to find an input value for instruction {} at address {}.", instruction, address),
+            IntcodeError::ChannelClosed { address, instruction } =>
+                write!(f, "Input channel closed for instruction {} at address {}.", instruction, address),
+            IntcodeError::StepLimitExceeded { address, instruction, limit } =>
+                write!(f, "Step limit of {} exceeded at instruction {} at address {}.", limit, instruction, address),
+            IntcodeError::MemoryLimitExceeded { address, instruction, limit } =>
+                write!(f, "Memory limit of {} cells exceeded by instruction {} at address {}.", limit, instruction, address),
+            IntcodeError::DeadlineExceeded { address, instruction } =>
+                write!(f, "Deadline passed before instruction {} at address {}.", instruction, address),
+            IntcodeError::Overflow { address, instruction } =>
+                write!(f, "Arithmetic overflow in instruction {} at address {}.", instruction, address),
+            IntcodeError::ExtensionFailed { address, instruction, message } =>
+                write!(f, "Instruction {} at address {} failed: {}", instruction, address, message),
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for IntcodeError<T> {}
+
+/// An error detected while decoding or executing an instruction, before the location of the
+/// instruction has been attached.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum Fault<T> {
+    UnknownOpcode,
+    BadParameterMode,
+    WriteToImmediate,
+    NegativeAddress(T),
+    InputExhausted,
+    ChannelClosed,
+    StepLimitExceeded(u64),
+    MemoryLimitExceeded(usize),
+    DeadlineExceeded,
+    Overflow,
+}
+
+impl<T> Fault<T> {
+    pub(crate) fn at(self, address: T, instruction: T) -> IntcodeError<T> {
+        match self {
+            Fault::UnknownOpcode => IntcodeError::UnknownOpcode { address, instruction },
+            Fault::BadParameterMode => IntcodeError::BadParameterMode { address, instruction },
+            Fault::WriteToImmediate => IntcodeError::WriteToImmediate { address, instruction },
+            Fault::NegativeAddress(target) => IntcodeError::NegativeAddress { address, instruction, target },
+            Fault::InputExhausted => IntcodeError::InputExhausted { address, instruction },
+            Fault::ChannelClosed => IntcodeError::ChannelClosed { address, instruction },
+            Fault::StepLimitExceeded(limit) => IntcodeError::StepLimitExceeded { address, instruction, limit },
+            Fault::MemoryLimitExceeded(limit) => IntcodeError::MemoryLimitExceeded { address, instruction, limit },
+            Fault::DeadlineExceeded => IntcodeError::DeadlineExceeded { address, instruction },
+            Fault::Overflow => IntcodeError::Overflow { address, instruction },
+        }
+    }
+}
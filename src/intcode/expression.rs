@@ -0,0 +1,320 @@
+use std::fmt;
+use std::str::FromStr;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, CheckedSub, FromPrimitive, ToPrimitive};
+
+use super::{Computer, IntcodeError};
+use super::error::Fault;
+
+/// A comparison between two values.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub fn test<T: Ord>(self, left: &T, right: &T) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">="
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Comparison::Equal, Comparison::NotEqual, Comparison::Less, Comparison::LessOrEqual, Comparison::Greater, Comparison::GreaterOrEqual]
+            .iter()
+            .find(|c| c.symbol() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown comparison '{}'.", s))
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+/// An operator joining two subexpressions.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Compare(Comparison),
+    And,
+    Or,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Subtract => write!(f, "-"),
+            Operator::Multiply => write!(f, "*"),
+            Operator::Compare(c) => write!(f, "{}", c),
+            Operator::And => write!(f, "&&"),
+            Operator::Or => write!(f, "||")
+        }
+    }
+}
+
+/// An expression over the registers and memory of a computer, such as `[rb+2] > 10 && loc != 4`.
+///
+/// Comparisons and logical operators evaluate to 1 for true and 0 for false, and any non-zero
+/// value counts as true. From loosest to tightest, the operators bind as `||`, `&&`, the
+/// comparisons, `+` and `-`, then `*`. `[e]` reads the memory at address `e`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Expression<T> {
+    Literal(T),
+    Loc,
+    RelativeBase,
+    Memory(Box<Expression<T>>),
+    Negate(Box<Expression<T>>),
+    Binary(Box<Expression<T>>, Operator, Box<Expression<T>>),
+}
+
+impl<T> Expression<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedSub + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Evaluates the expression against the current state of a computer. Arithmetic that
+    /// overflows fails with `IntcodeError::Overflow`, reported at the current location.
+    pub fn evaluate(&self, computer: &Computer<T>) -> Result<T, IntcodeError<T>> {
+        let truth = |b: bool| if b { T::one() } else { T::zero() };
+        let checked = |result: Option<T>| result.ok_or_else(|| computer.fault(Fault::Overflow));
+        Ok(match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Loc => computer.loc(),
+            Expression::RelativeBase => computer.relative_base(),
+            Expression::Memory(address) => computer.read(address.evaluate(computer)?)?,
+            Expression::Negate(e) => checked(T::zero().checked_sub(&e.evaluate(computer)?))?,
+            Expression::Binary(left, Operator::And, right) =>
+                truth(!left.evaluate(computer)?.is_zero() && !right.evaluate(computer)?.is_zero()),
+            Expression::Binary(left, Operator::Or, right) =>
+                truth(!left.evaluate(computer)?.is_zero() || !right.evaluate(computer)?.is_zero()),
+            Expression::Binary(left, operator, right) => {
+                let (l, r) = (left.evaluate(computer)?, right.evaluate(computer)?);
+                match operator {
+                    Operator::Add => checked(l.checked_add(&r))?,
+                    Operator::Subtract => checked(l.checked_sub(&r))?,
+                    Operator::Multiply => checked(l.checked_mul(&r))?,
+                    Operator::Compare(c) => truth(c.test(&l, &r)),
+                    Operator::And | Operator::Or => unreachable!()
+                }
+            }
+        })
+    }
+
+    /// Evaluates the expression as a condition.
+    pub fn is_true(&self, computer: &Computer<T>) -> Result<bool, IntcodeError<T>> {
+        Ok(!self.evaluate(computer)?.is_zero())
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Expression<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Literal(value) => write!(f, "{}", value),
+            Expression::Loc => write!(f, "loc"),
+            Expression::RelativeBase => write!(f, "rb"),
+            Expression::Memory(address) => write!(f, "[{}]", address),
+            Expression::Negate(e) => write!(f, "-{}", e),
+            Expression::Binary(left, operator, right) => write!(f, "({} {} {})", left, operator, right)
+        }
+    }
+}
+
+impl<T: FromStr> FromStr for Expression<T> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        let expression = parser.or()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("Unexpected '{}' in expression.", token))
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    const SYMBOLS: [&str; 15] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "[", "]", "(", ")"];
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let length = match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            Some(symbol) => symbol.len(),
+            None => rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len())
+        };
+        if length == 0 {
+            return Err(format!("Unexpected character '{}' in expression.", rest.chars().next().unwrap()));
+        }
+        tokens.push(String::from(&rest[..length]));
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            Some(t) => Err(format!("Expected '{}' but found '{}'.", token, t)),
+            None => Err(format!("Expected '{}' at end of expression.", token))
+        }
+    }
+
+    /// Parses a sequence of operands joined by any of the given operators, associating to the left.
+    fn chain<T, F>(&mut self, operators: &[(&str, Operator)], mut operand: F) -> Result<Expression<T>, String>
+    where
+        F: FnMut(&mut Self) -> Result<Expression<T>, String>,
+    {
+        let mut expression = operand(self)?;
+        while let Some(&(_, operator)) = operators.iter().find(|(symbol, _)| Some(*symbol) == self.peek()) {
+            self.position += 1;
+            expression = Expression::Binary(Box::new(expression), operator, Box::new(operand(self)?));
+        }
+        Ok(expression)
+    }
+
+    fn or<T: FromStr>(&mut self) -> Result<Expression<T>, String> {
+        self.chain(&[("||", Operator::Or)], |p| p.and())
+    }
+
+    fn and<T: FromStr>(&mut self) -> Result<Expression<T>, String> {
+        self.chain(&[("&&", Operator::And)], |p| p.comparison())
+    }
+
+    fn comparison<T: FromStr>(&mut self) -> Result<Expression<T>, String> {
+        let left = self.sum()?;
+        match self.peek().and_then(|t| t.parse::<Comparison>().ok()) {
+            Some(c) => {
+                self.position += 1;
+                Ok(Expression::Binary(Box::new(left), Operator::Compare(c), Box::new(self.sum()?)))
+            },
+            None => Ok(left)
+        }
+    }
+
+    fn sum<T: FromStr>(&mut self) -> Result<Expression<T>, String> {
+        self.chain(&[("+", Operator::Add), ("-", Operator::Subtract)], |p| p.product())
+    }
+
+    fn product<T: FromStr>(&mut self) -> Result<Expression<T>, String> {
+        self.chain(&[("*", Operator::Multiply)], |p| p.term())
+    }
+
+    fn term<T: FromStr>(&mut self) -> Result<Expression<T>, String> {
+        match self.next() {
+            Some("loc") => Ok(Expression::Loc),
+            Some("rb") => Ok(Expression::RelativeBase),
+            Some("-") => Ok(Expression::Negate(Box::new(self.term()?))),
+            Some("[") => {
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Expression::Memory(Box::new(address)))
+            },
+            Some("(") => {
+                let expression = self.or()?;
+                self.expect(")")?;
+                Ok(expression)
+            },
+            Some(text) => text.parse().map(Expression::Literal).map_err(|_| format!("Invalid value '{}' in expression.", text)),
+            None => Err(String::from("Unexpected end of expression."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str, computer: &Computer<i64>) -> Result<i64, String> {
+        text.parse::<Expression<i64>>()?.evaluate(computer).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_precedence() {
+        let computer = Computer::new(vec![], None, None);
+        assert_eq!(Ok(7), evaluate("1 + 2 * 3", &computer));
+        assert_eq!(Ok(9), evaluate("(1 + 2) * 3", &computer));
+        assert_eq!(Ok(-1), evaluate("2 - 4 + 1", &computer));
+        assert_eq!(Ok(1), evaluate("1 + 1 == 2 && 3 > 2 || 0", &computer));
+        assert_eq!(Ok(0), evaluate("1 < 0 || 2 != 2", &computer));
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut computer = Computer::new(vec![109, 3, 99, 10, 20], None, None);
+        computer.step().unwrap();
+        assert_eq!(Ok(2), evaluate("loc", &computer));
+        assert_eq!(Ok(3), evaluate("rb", &computer));
+        assert_eq!(Ok(20), evaluate("[rb+1]", &computer));
+        assert_eq!(Ok(99), evaluate("[[1] - 1]", &computer));
+        assert_eq!(Ok(1), evaluate("[4] >= 20 && loc == 2", &computer));
+        assert!(evaluate("[-1]", &computer).is_err());
+    }
+
+    #[test]
+    fn test_overflow_is_an_error() {
+        let computer = Computer::new(vec![2, 0, 0, 0, 99], None, None);
+        assert_eq!(Err(String::from("Arithmetic overflow in instruction 2 at address 0.")), evaluate("[0] * 9223372036854775807", &computer));
+        assert!(evaluate("9223372036854775807 + 1", &computer).is_err());
+        assert!(evaluate("-9223372036854775807 - 2", &computer).is_err());
+        assert!(evaluate("-(-9223372036854775807 - 1)", &computer).is_err());
+        assert_eq!(Ok(i64::MIN), evaluate("-9223372036854775807 - 1", &computer));
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let expression: Expression<i64> = "[rb - 2] <= -5 || loc == 3 * 2".parse().unwrap();
+        assert_eq!("(([(rb - 2)] <= -5) || (loc == (3 * 2)))", expression.to_string());
+        assert_eq!(Ok(expression.clone()), expression.to_string().parse());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("".parse::<Expression<i64>>().is_err());
+        assert!("[1".parse::<Expression<i64>>().is_err());
+        assert!("1 2".parse::<Expression<i64>>().is_err());
+        assert!("loc == x".parse::<Expression<i64>>().is_err());
+        assert!("loc ? 1".parse::<Expression<i64>>().is_err());
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Argument, ArgumentKind, CallResult, Computer, ComputerInput, ComputerOutput, Instruction, IntcodeError, convert, offset, to_address};
+use super::error::Fault;
+use super::memory::Memory;
+
+/// The opcodes with a meaning in standard Intcode, which can't be redefined.
+const BUILT_IN: [u8; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+
+/// The modes a parameter of an extension opcode may be given in.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Parameter {
+    /// A value to read, in any mode.
+    Value,
+    /// A location to write to, in position or relative mode.
+    Address,
+    /// A constant, such as a syscall number, in immediate mode.
+    Constant,
+}
+
+/// What the computer does once the handler for an extension opcode has returned.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Effect<T> {
+    /// Carry on with the next instruction.
+    Continue,
+    /// Carry on at the given address.
+    Jump(T),
+    /// Halt, as `hlt` does.
+    Halt,
+    /// Halt, leaving an exit code to be read with `Computer::exit_code`.
+    Exit(T),
+}
+
+type Handler<T> = dyn Fn(&mut Context<T>) -> Result<Effect<T>, IntcodeError<T>> + Send + Sync;
+
+/// The definition of an extension opcode: its mnemonic, its parameters and what it does.
+pub struct Opcode<T: Integer + Signed> {
+    mnemonic: &'static str,
+    parameters: Vec<Parameter>,
+    handler: Arc<Handler<T>>,
+}
+
+impl<T: Integer + Signed> Clone for Opcode<T> {
+    fn clone(&self) -> Self {
+        Opcode { mnemonic: self.mnemonic, parameters: self.parameters.clone(), handler: Arc::clone(&self.handler) }
+    }
+}
+
+impl<T> Opcode<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Defines an opcode. The handler must be `Send` and `Sync` so that a computer using it can
+    /// still be sent to another thread.
+    pub fn new<F>(mnemonic: &'static str, parameters: &[Parameter], handler: F) -> Self
+    where
+        F: Fn(&mut Context<T>) -> Result<Effect<T>, IntcodeError<T>> + Send + Sync + 'static,
+    {
+        Opcode { mnemonic, parameters: parameters.to_vec(), handler: Arc::new(handler) }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.mnemonic
+    }
+
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// Decodes an instruction using this opcode, checking its parameter modes.
+    pub(super) fn decode(&self, opcode: u8, location: T, argument_types: Vec<ArgumentKind>, memory: &dyn Memory<T>, relative_base: T) -> Result<Instruction<T>, Fault<T>> {
+        let arguments = self.parameters.iter().enumerate().map(|(n, parameter)| {
+            let kind = argument_types.get(n).cloned().unwrap_or(ArgumentKind::Position);
+            match (parameter, &kind) {
+                (Parameter::Address, ArgumentKind::Immediate) => return Err(Fault::WriteToImmediate),
+                (Parameter::Constant, ArgumentKind::Position) | (Parameter::Constant, ArgumentKind::Relative) => return Err(Fault::BadParameterMode),
+                _ => {}
+            }
+            let value = memory.read(to_address(&offset(&location, n + 1)?)?);
+            Ok(Argument::new(value, Some(&kind), relative_base.clone()))
+        }).collect::<Result<_, _>>()?;
+        Ok(Instruction::Extension { opcode, mnemonic: self.mnemonic, arguments })
+    }
+}
+
+/// What the handler for an extension instruction did, so that it can be traced, logged and
+/// undone like the effects of a built-in instruction.
+pub(super) struct Activity<T> {
+    /// The memory cells written, as (address, old value, new value), in order.
+    pub(super) writes: Vec<(T, T, T)>,
+    pub(super) inputs: Vec<T>,
+    pub(super) outputs: Vec<T>,
+}
+
+/// The view of a computer given to the handler for an extension opcode.
+///
+/// Failures are reported against the extension instruction, so a handler can pass them straight
+/// back with `?`.
+pub struct Context<'a, T: Integer + Signed> {
+    address: T,
+    instruction: T,
+    arguments: &'a [Argument<T>],
+    memory: &'a mut dyn Memory<T>,
+    input: &'a mut ComputerInput<T>,
+    replay: &'a mut VecDeque<T>,
+    output: &'a mut ComputerOutput<T>,
+    alt_output: &'a mut VecDeque<T>,
+    relative_base: &'a mut T,
+    activity: Activity<T>,
+}
+
+impl<'a, T> Context<'a, T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// The address of the instruction being run.
+    pub fn address(&self) -> T {
+        self.address.clone()
+    }
+
+    /// The raw value of the instruction being run.
+    pub fn instruction(&self) -> T {
+        self.instruction.clone()
+    }
+
+    pub fn arguments(&self) -> &[Argument<T>] {
+        self.arguments
+    }
+
+    /// Reads the value of the nth parameter, counting from zero.
+    pub fn get(&self, n: usize) -> Result<T, IntcodeError<T>> {
+        self.argument(n)?.get(self.memory).map_err(|f| self.fault(f))
+    }
+
+    /// Writes a value to the location given by the nth parameter, counting from zero.
+    pub fn set(&mut self, n: usize, value: T) -> Result<(), IntcodeError<T>> {
+        match self.argument(n)?.target().map_err(|f| self.fault(f))? {
+            Some(address) => self.write(&address, value),
+            None => Err(self.fault(Fault::WriteToImmediate))
+        }
+    }
+
+    /// Reads the value at an address. Memory that has never been written reads as zero.
+    pub fn read(&self, address: &T) -> Result<T, IntcodeError<T>> {
+        Ok(self.memory.read(to_address(address).map_err(|f| self.fault(f))?))
+    }
+
+    pub fn write(&mut self, address: &T, value: T) -> Result<(), IntcodeError<T>> {
+        let index = to_address(address).map_err(|f| self.fault(f))?;
+        let old = self.memory.read(index);
+        self.memory.write(index, value.clone());
+        self.activity.writes.push((address.clone(), old, value));
+        Ok(())
+    }
+
+    /// Takes the next input value, waiting for one if input comes from a channel.
+    pub fn input(&mut self) -> Result<T, IntcodeError<T>> {
+        let value = match self.replay.pop_front() {
+            Some(value) => value,
+            None => match self.input {
+                ComputerInput::Queue(q) => q.pop_front().ok_or(Fault::InputExhausted),
+                ComputerInput::Channel(rx) => rx.recv().map_err(|_| Fault::ChannelClosed)
+            }.map_err(|f| self.fault(f))?
+        };
+        self.activity.inputs.push(value.clone());
+        Ok(value)
+    }
+
+    pub fn output(&mut self, value: T) {
+        self.activity.outputs.push(value.clone());
+        match self.output {
+            ComputerOutput::Queue(q) => q.push_back(value),
+            ComputerOutput::Channel(tx) => if let Err(e) = tx.send(value) {
+                self.alt_output.push_back(e.0);
+            }
+        }
+    }
+
+    pub fn relative_base(&self) -> T {
+        self.relative_base.clone()
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: T) {
+        *self.relative_base = relative_base;
+    }
+
+    /// Creates an error reporting that the instruction failed for the given reason.
+    pub fn fail(&self, message: &str) -> IntcodeError<T> {
+        IntcodeError::ExtensionFailed { address: self.address(), instruction: self.instruction(), message: String::from(message) }
+    }
+
+    fn fault(&self, fault: Fault<T>) -> IntcodeError<T> {
+        fault.at(self.address(), self.instruction())
+    }
+
+    fn argument(&self, n: usize) -> Result<&Argument<T>, IntcodeError<T>> {
+        self.arguments.get(n).ok_or_else(|| self.fail(&format!("it has no parameter {}", n)))
+    }
+}
+
+impl<T> Computer<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Adds an opcode to the instruction set of the computer, replacing any earlier definition.
+    /// The opcodes of standard Intcode can't be redefined.
+    ///
+    /// The memory an extension instruction writes and the input and output it handles are
+    /// recorded, traced and logged like those of any other instruction. An extension that reads
+    /// input fails if the input queue is empty, rather than `resume` reporting that it needs
+    /// input.
+    pub fn register_opcode(&mut self, opcode: u8, definition: Opcode<T>) -> Result<(), String> {
+        if opcode == 0 || opcode > 99 || BUILT_IN.contains(&opcode) {
+            return Err(format!("Opcode {} can't be used for an extension.", opcode));
+        }
+        self.decoded.allow_length(definition.parameters().len() + 1);
+        self.extensions.insert(opcode, definition);
+        self.decoded.clear();
+        Ok(())
+    }
+
+    /// The definition of an extension opcode, if one has been registered.
+    pub fn extension(&self, opcode: u8) -> Option<&Opcode<T>> {
+        self.extensions.get(&opcode)
+    }
+
+    /// The exit code given by an extension instruction that halted the computer.
+    pub fn exit_code(&self) -> Option<T> {
+        self.exit_code.clone()
+    }
+
+    /// Runs the handler for an extension instruction at the current location, returning what it
+    /// did along with the result.
+    pub(super) fn call_extension(&mut self, opcode: u8, arguments: &[Argument<T>]) -> Result<(CallResult<T>, Activity<T>), IntcodeError<T>> {
+        let handler = Arc::clone(&self.extensions[&opcode].handler);
+        let mut context = Context {
+            address: self.loc.clone(),
+            instruction: self.read_raw(&self.loc),
+            arguments,
+            memory: self.memory.as_mut(),
+            input: &mut self.input,
+            replay: &mut self.replay,
+            output: &mut self.output,
+            alt_output: &mut self.alt_output,
+            relative_base: &mut self.relative_base,
+            activity: Activity { writes: vec![], inputs: vec![], outputs: vec![] },
+        };
+        let effect = handler(&mut context);
+        let activity = context.activity;
+        for (address, _, _) in &activity.writes {
+            self.decoded.invalidate(to_address(address).unwrap_or(0));
+        }
+
+        let result = match effect? {
+            Effect::Continue => CallResult::Step(convert(arguments.len() + 1)),
+            Effect::Jump(target) => CallResult::Jump(target),
+            Effect::Halt => CallResult::Stop,
+            Effect::Exit(code) => {
+                self.exit_code = Some(code);
+                CallResult::Stop
+            }
+        };
+        Ok((result, activity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use super::super::iolog::IoRecorder;
+
+    /// Squares its first parameter into its second.
+    fn square() -> Opcode<i64> {
+        Opcode::new("sq", &[Parameter::Value, Parameter::Address], |context| {
+            let value = context.get(0)?;
+            context.set(1, value * value)?;
+            Ok(Effect::Continue)
+        })
+    }
+
+    /// Fails unless its two parameters are equal.
+    fn assert() -> Opcode<i64> {
+        Opcode::new("assert", &[Parameter::Value, Parameter::Value], |context| {
+            let (left, right) = (context.get(0)?, context.get(1)?);
+            if left == right {
+                Ok(Effect::Continue)
+            } else {
+                Err(context.fail(&format!("expected {} but found {}", right, left)))
+            }
+        })
+    }
+
+    /// Makes system call 1, which reads input into its second parameter, or 2, which jumps to it.
+    fn syscall() -> Opcode<i64> {
+        Opcode::new("sys", &[Parameter::Constant, Parameter::Address], |context| {
+            match context.get(0)? {
+                1 => {
+                    let value = context.input()?;
+                    context.set(1, value)?;
+                    Ok(Effect::Continue)
+                },
+                2 => Ok(Effect::Jump(context.get(1)?)),
+                _ => Err(context.fail("unknown system call"))
+            }
+        })
+    }
+
+    fn extended(program: Vec<i64>, opcodes: Vec<(u8, Opcode<i64>)>) -> Computer<i64> {
+        let mut computer = Computer::new(program, None, None);
+        for (opcode, definition) in opcodes {
+            computer.register_opcode(opcode, definition).unwrap();
+        }
+        computer
+    }
+
+    #[test]
+    fn test_extension_opcode() {
+        let mut computer = extended(vec![110, -7, 7, 4, 7, 99, 0, 0], vec![(10, square())]);
+        computer.run().unwrap();
+        assert_eq!(vec![49], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_extension_display() {
+        let computer = extended(vec![2110, 3, -1, 99], vec![(10, square())]);
+        assert_eq!("sq #3, rb-1", computer.current_instruction().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_extension_failure() {
+        let mut computer = extended(vec![1111, 2, 2, 1111, 2, 3, 99], vec![(11, assert())]);
+        let error = computer.run().unwrap_err();
+        assert_eq!(IntcodeError::ExtensionFailed { address: 3, instruction: 1111, message: String::from("expected 3 but found 2") }, error);
+        assert_eq!("Instruction 1111 at address 3 failed: expected 3 but found 2", error.to_string());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let mut computer = extended(vec![112, 3, 104, 1], vec![(12, Opcode::new("exit", &[Parameter::Value], |c| Ok(Effect::Exit(c.get(0)?))))]);
+        computer.run().unwrap();
+        assert_eq!(Some(3), computer.exit_code());
+        assert!(!computer.is_running());
+        assert!(computer.output().is_empty());
+    }
+
+    #[test]
+    fn test_syscalls_and_parameter_rules() {
+        let mut computer = extended(vec![113, 1, 10, 113, 2, 9, 104, 1, 99, 8, 0], vec![(13, syscall())]);
+        computer.push_input(99).unwrap();
+        computer.run().unwrap();
+        assert_eq!(Ok(99), computer.read(10));
+        assert!(computer.output().is_empty());
+
+        let mut computer = extended(vec![13, 1, 9], vec![(13, syscall())]);
+        assert_eq!(Err(IntcodeError::BadParameterMode { address: 0, instruction: 13 }), computer.run());
+        let mut computer = extended(vec![1113, 1, 9], vec![(13, syscall())]);
+        assert_eq!(Err(IntcodeError::WriteToImmediate { address: 0, instruction: 1113 }), computer.run());
+    }
+
+    #[test]
+    fn test_debug_print() {
+        let printed = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&printed);
+        let debug = Opcode::new("dbg", &[Parameter::Value], move |context| {
+            log.lock().unwrap().push(format!("{} at {}", context.get(0)?, context.address()));
+            Ok(Effect::Continue)
+        });
+        let mut computer = extended(vec![14, 0, 114, -2, 99], vec![(14, debug)]);
+        computer.run().unwrap();
+        assert_eq!(vec!["14 at 0", "-2 at 2"], *printed.lock().unwrap());
+    }
+
+    #[test]
+    fn test_extension_writes_invalidate_decoded_code() {
+        // Squares the parameter of its own output instruction before each pass.
+        let program = vec![10, 4, 4, 104, 2, 1001, 13, -1, 13, 1005, 13, 0, 99, 2];
+        let mut computer = extended(program, vec![(10, square())]);
+        computer.run().unwrap();
+        assert_eq!(vec![4, 16], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_writes_to_the_tail_of_a_long_instruction() {
+        // Outputs the sum of its four parameters, then adds 36 to the last one and runs it again.
+        let sum = Opcode::new("sum", &[Parameter::Value; 4], |context| {
+            let total = (0..4).map(|n| context.get(n)).sum::<Result<i64, _>>()?;
+            context.output(total);
+            Ok(Effect::Continue)
+        });
+        let program = vec![111115, 1, 2, 3, 4, 1001, 4, 36, 4, 1007, 4, 41, 17, 1005, 17, 0, 99, 0];
+        let mut computer = extended(program, vec![(15, sum)]);
+        computer.run().unwrap();
+        assert_eq!(vec![10, 46], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_step_back_over_extension() {
+        let mut computer = extended(vec![113, 1, 4, 99, 0], vec![(13, syscall())]);
+        computer.push_input(7).unwrap();
+        computer.start_recording();
+        computer.step().unwrap();
+        assert_eq!(Ok(7), computer.read(4));
+
+        let change = computer.step_back().unwrap();
+        assert_eq!((vec![(4, 0, 7)], vec![7]), (change.writes, change.inputs));
+        assert_eq!(Ok(0), computer.read(4));
+        computer.step().unwrap();
+        assert_eq!(Ok(7), computer.read(4));
+    }
+
+    #[test]
+    fn test_extension_io_is_recorded() {
+        let double = Opcode::new("dbl", &[], |context| {
+            let value = context.input()?;
+            context.output(value * 2);
+            Ok(Effect::Continue)
+        });
+        let recorder = IoRecorder::new();
+        let mut computer = extended(vec![16, 16, 99], vec![(16, double)]);
+        computer.push_input(3).unwrap();
+        computer.push_input(4).unwrap();
+        computer.set_tracer(recorder.tracer("A"));
+        computer.start_recording();
+        computer.run().unwrap();
+        assert_eq!((vec![3, 4], vec![6, 8]), (recorder.log().inputs("A"), recorder.log().outputs("A")));
+
+        for _ in 0..3 {
+            computer.step_back().unwrap();
+        }
+        assert!(computer.output().is_empty());
+        computer.run().unwrap();
+        assert_eq!(vec![6, 8], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_undeclared_parameter() {
+        let mut computer = extended(vec![17, 99], vec![(17, Opcode::new("bad", &[], |c| c.set(0, 1).map(|_| Effect::Continue)))]);
+        let error = IntcodeError::ExtensionFailed { address: 0, instruction: 17, message: String::from("it has no parameter 0") };
+        assert_eq!(Err(error), computer.run());
+    }
+
+    #[test]
+    fn test_register_rejects_built_in_opcodes() {
+        let mut computer = Computer::new(vec![99], None, None);
+        assert!(computer.register_opcode(1, square()).is_err());
+        assert!(computer.register_opcode(99, square()).is_err());
+        assert!(computer.register_opcode(100, square()).is_err());
+        assert!(computer.register_opcode(42, square()).is_ok());
+        assert_eq!(Some("sq"), computer.extension(42).map(|o| o.mnemonic()));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use itertools::Itertools;
+use num::{BigInt, Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, IntcodeError, Limits, RunStatus, analysis, disassembler, translator};
+use super::memory::MemoryKind;
+
+/// A small, fast pseudo-random number generator (SplitMix64), so that fuzzing runs can be
+/// repeated from their seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// A value in `low..high`.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + self.below((high - low) as u64) as i64
+    }
+}
+
+/// Generates a program made of valid instructions, whose parameters mostly refer to addresses
+/// within or just beyond the program.
+pub fn well_formed(rng: &mut Rng, length: usize) -> Vec<i64> {
+    let span = length as i64 + 8;
+    let mut program = vec![];
+    while program.len() < length {
+        let (opcode, reads, writes) = match rng.below(11) {
+            0 => (1, 2, 1),
+            1 => (2, 2, 1),
+            2 => (3, 0, 1),
+            3 => (4, 1, 0),
+            4 => (5, 2, 0),
+            5 => (6, 2, 0),
+            6 => (7, 2, 1),
+            7 => (8, 2, 1),
+            8 | 9 => (9, 1, 0),
+            _ => (99, 0, 0),
+        };
+        let mut modes: Vec<i64> = (0..reads).map(|_| rng.range(0, 3)).collect();
+        modes.extend((0..writes).map(|_| 2 * rng.range(0, 2)));
+        program.push(opcode + modes.iter().rev().fold(0, |acc, mode| acc * 10 + mode) * 100);
+        for mode in modes {
+            program.push(match mode {
+                1 => rng.range(-10, span),
+                2 => rng.range(-5, 6),
+                _ => rng.range(0, span),
+            });
+        }
+    }
+    program
+}
+
+/// Generates a program of arbitrary words: a mixture of instructions, bad opcodes and modes,
+/// negative and extreme values, and instructions cut short by the end of the program.
+pub fn malformed(rng: &mut Rng, length: usize) -> Vec<i64> {
+    (0..length).map(|_| match rng.below(8) {
+        0 => rng.range(-100, 100),
+        1 => rng.range(0, 30000),
+        2 => [i64::MAX, i64::MIN, i64::MAX - 1, -1, 0, 1 << 40][rng.below(6) as usize],
+        3 => rng.range(1, 10) + 100 * rng.range(0, 300),
+        _ => rng.range(0, length as i64 + 8),
+    }).collect()
+}
+
+/// Everything observable about a finished run.
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct Outcome<T> {
+    result: Result<T, IntcodeError<T>>,
+    output: Vec<T>,
+    loc: T,
+    steps: u64,
+    /// The cells holding something other than zero.
+    memory: Vec<(usize, T)>,
+}
+
+impl<T> Outcome<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    fn of(computer: &Computer<T>, result: Result<T, IntcodeError<T>>, output: Vec<T>) -> Self {
+        Outcome {
+            result,
+            output,
+            loc: computer.loc(),
+            steps: computer.steps(),
+            memory: computer.memory.cells().into_iter().filter(|(_, value)| !value.is_zero()).collect(),
+        }
+    }
+
+    /// Describes the outcome in a form that can be compared across integer types.
+    fn summary(&self) -> String {
+        let result = match &self.result {
+            Ok(value) => value.to_string(),
+            Err(e) => e.to_string()
+        };
+        let memory = self.memory.iter().map(|(address, value)| format!("{}:{}", address, value)).join(",");
+        format!("{} output [{}] at {} after {} steps, memory [{}]", result, self.output.iter().join(","), self.loc, self.steps, memory)
+    }
+}
+
+/// A program that broke one of the invariants.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Failure {
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+    pub problem: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n  Program: {}\n  Input: {}", self.problem, self.program.iter().join(","), self.input.iter().join(","))
+    }
+}
+
+/// Runs a program every way the computer allows, with overflow checks on and a step limit, and
+/// checks that none of them panics and all of them agree:
+///
+/// * dense and sparse memory give identical results;
+/// * `resume` behaves like `run`, stopping for input where `run` finds none;
+/// * big integers give the same results as `i64`, unless `i64` arithmetic overflowed;
+/// * undoing every recorded step returns the computer to the original program;
+/// * the disassembler, control-flow analysis and translator accept the program.
+pub fn check(program: &[i64], input: &[i64], max_steps: u64) -> Result<(), String> {
+    let reference = guard("Dense memory", || run(program, input, max_steps, MemoryKind::Dense))?;
+
+    let sparse = guard("Sparse memory", || run(program, input, max_steps, MemoryKind::Sparse))?;
+    compare("Sparse memory", &reference.summary(), &sparse.summary())?;
+
+    let resumed = guard("Resume", || resume(program, input, max_steps))?;
+    let expected = match &reference.result {
+        Err(IntcodeError::InputExhausted { .. }) => Outcome { result: Ok(0), ..reference.clone() },
+        _ => reference.clone()
+    };
+    compare("Resume", &expected.summary(), &resumed.summary())?;
+
+    if !matches!(reference.result, Err(IntcodeError::Overflow { .. })) {
+        let big = guard("Big integers", || big(program, input, max_steps))?;
+        compare("Big integers", &reference.summary(), &big.summary())?;
+    }
+
+    guard("History", || undo(program, input, max_steps))??;
+    guard("Static tools", || {
+        disassembler::listing(program);
+        analysis::ControlFlowGraph::new(program).to_dot();
+        let _ = translator::translate(program, "fuzz");
+    })
+}
+
+/// Calls a function, turning a panic into an error.
+fn guard<R>(name: &str, f: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload.downcast_ref::<String>().cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        format!("{} panicked: {}", name, message)
+    })
+}
+
+fn compare(name: &str, expected: &str, actual: &str) -> Result<(), String> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!("{} disagrees with the reference run.\n  Expected: {}\n  Actual: {}", name, expected, actual))
+    }
+}
+
+fn computer<T>(program: &[i64], input: &[i64], max_steps: u64, memory: MemoryKind) -> Computer<T>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let widen = |values: &[i64]| values.iter().map(|&v| T::from_i64(v).unwrap()).collect::<VecDeque<T>>();
+    let mut computer = Computer::with_memory(memory.load(widen(program).into()), Some(ComputerInput::Queue(widen(input))), None);
+    computer.set_overflow_checks(true);
+    computer.set_limits(Limits { max_steps: Some(max_steps), ..Limits::default() });
+    computer
+}
+
+fn run(program: &[i64], input: &[i64], max_steps: u64, memory: MemoryKind) -> Outcome<i64> {
+    let mut computer = computer(program, input, max_steps, memory);
+    let result = computer.run();
+    let output = computer.output().into();
+    Outcome::of(&computer, result, output)
+}
+
+/// Runs a program with `resume`. Running out of input is reported as a successful result of
+/// zero, matching the reference run's `InputExhausted` error in everything else.
+fn resume(program: &[i64], input: &[i64], max_steps: u64) -> Outcome<i64> {
+    let mut computer = computer(program, input, max_steps, MemoryKind::Dense);
+    let mut output = vec![];
+    let result = loop {
+        match computer.resume() {
+            Ok(RunStatus::ProducedOutput(value)) => output.push(value),
+            Ok(RunStatus::NeedsInput) => break Ok(0),
+            Ok(RunStatus::Halted) => break computer.read(0),
+            Err(e) => break Err(e)
+        }
+    };
+    Outcome::of(&computer, result, output)
+}
+
+fn big(program: &[i64], input: &[i64], max_steps: u64) -> Outcome<BigInt> {
+    let mut computer = computer(program, input, max_steps, MemoryKind::Dense);
+    let result = computer.run();
+    let output = computer.output().into();
+    Outcome::of(&computer, result, output)
+}
+
+/// Runs a program while recording, then steps back over everything it did.
+fn undo(program: &[i64], input: &[i64], max_steps: u64) -> Result<(), String> {
+    let mut computer: Computer<i64> = computer(program, input, max_steps, MemoryKind::Dense);
+    computer.start_recording();
+    let _ = computer.run();
+    while computer.step_back().is_some() {}
+
+    let original: Vec<(usize, i64)> = program.iter().cloned().enumerate().filter(|(_, value)| *value != 0).collect();
+    let restored: Vec<(usize, i64)> = computer.memory.cells().into_iter().filter(|(_, value)| *value != 0).collect();
+    if computer.loc() != 0 || computer.steps() != 0 || restored != original {
+        return Err(format!("Stepping back to the start left the computer at {} after {} steps with memory {:?}.", computer.loc(), computer.steps(), restored));
+    }
+    Ok(())
+}
+
+/// Generates and checks programs, half well-formed and half malformed, returning those that
+/// broke an invariant.
+pub fn fuzz(seed: u64, iterations: usize, max_steps: u64) -> Vec<Failure> {
+    let mut rng = Rng::new(seed);
+    let mut failures = vec![];
+    for iteration in 0..iterations {
+        let length = rng.range(1, 64) as usize;
+        let program = if iteration % 2 == 0 { well_formed(&mut rng, length) } else { malformed(&mut rng, length) };
+        let input: Vec<i64> = (0..rng.below(6)).map(|_| rng.range(-20, 100)).collect();
+        if let Err(problem) = check(&program, &input, max_steps) {
+            failures.push(Failure { program, input, problem });
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzz() {
+        let failures = fuzz(2019, 2000, 500);
+        assert!(failures.is_empty(), "{} programs failed, the first being:\n{}", failures.len(), failures[0]);
+    }
+
+    #[test]
+    fn test_generators() {
+        let mut rng = Rng::new(1);
+        let program = well_formed(&mut rng, 40);
+        assert!(program.len() >= 40);
+        assert!(disassembler::disassemble(&program).len() > 1);
+        assert_eq!(40, malformed(&mut rng, 40).len());
+        assert_ne!(well_formed(&mut Rng::new(1), 40), well_formed(&mut Rng::new(2), 40));
+    }
+
+    #[test]
+    fn test_guard_reports_panics() {
+        let problem = guard("Test", || panic!("oops")).unwrap_err();
+        assert_eq!("Test panicked: oops", problem);
+    }
+
+    #[test]
+    fn test_addresses_near_the_limit() {
+        let max = i64::MAX;
+        for program in &[
+            vec![109, max, 204, 1, 99],
+            vec![109, -1, 109, i64::MIN, 99],
+            vec![109, max, 21101, 1, 1, 1, 99],
+            vec![1101, 0, 1105, max - 1, 1105, 1, max - 1],
+            vec![1101, 0, 1105, max - 2, 1101, 0, 1, max - 1, 1101, 0, max, max, 1105, 1, max - 2],
+            vec![1101, 0, 99, max, 1105, 1, max],
+            vec![1101, 0, 104, max - 1, 1105, 1, max - 1],
+        ] {
+            if let Err(problem) = check(program, &[], 100) {
+                panic!("{:?}: {}", program, problem);
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, ComputerOutput, Instruction, to_address};
+use super::extension::Activity;
+
+/// The effect of one recorded step, holding enough to undo it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Change<T> {
+    /// The location of the instruction that was executed.
+    pub loc: T,
+    /// The relative base before the instruction was executed.
+    pub relative_base: T,
+    /// The memory cells the instruction wrote, as (address, old value, new value), in the order
+    /// they were written. Only extension instructions write more than one.
+    pub writes: Vec<(T, T, T)>,
+    /// The input values the instruction consumed, in order.
+    pub inputs: Vec<T>,
+    /// The values the instruction output, in order.
+    pub outputs: Vec<T>,
+    /// How many of the outputs are still at the back of the output queue, to be removed when the
+    /// step is undone. Values sent on a channel or already returned by `resume` aren't.
+    pub queued: usize,
+}
+
+impl<T> Computer<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Starts recording every step so that it can be undone with `step_back`.
+    ///
+    /// Only the effects of executing instructions are recorded. Changes made from outside, such as
+    /// with `write` or `set_loc`, are kept when stepping back past them.
+    pub fn start_recording(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(vec![]);
+        }
+    }
+
+    /// Stops recording and discards the history.
+    pub fn stop_recording(&mut self) {
+        self.history = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.history.is_some()
+    }
+
+    /// Returns the recorded steps, oldest first.
+    pub fn history(&self) -> &[Change<T>] {
+        self.history.as_deref().unwrap_or(&[])
+    }
+
+    /// Undoes the most recent recorded step, returning what it changed, or `None` if there is
+    /// nothing left to undo.
+    ///
+    /// Input consumed by the step is put back so that running forward again reads the same
+    /// values. Output is removed from the output queue; values already sent on an output channel
+    /// can't be recalled, and are sent again if the step is replayed.
+    pub fn step_back(&mut self) -> Option<Change<T>> {
+        let change = self.history.as_mut()?.pop()?;
+        for (address, old, _) in change.writes.iter().rev() {
+            let address = to_address(address).unwrap_or(0);
+            self.memory.write(address, old.clone());
+            self.decoded.invalidate(address);
+        }
+        for value in change.inputs.iter().rev() {
+            match &mut self.input {
+                ComputerInput::Queue(q) => q.push_front(value.clone()),
+                ComputerInput::Channel(_) => self.replay.push_front(value.clone())
+            }
+        }
+        if let ComputerOutput::Queue(q) = &mut self.output {
+            q.truncate(q.len().saturating_sub(change.queued));
+        }
+        self.loc = change.loc.clone();
+        self.relative_base = change.relative_base.clone();
+        self.running = true;
+        self.exit_code = None;
+        self.steps -= 1;
+        Some(change)
+    }
+
+    /// Captures the state that an instruction is about to change, if recording.
+    pub(super) fn begin_change(&self, instruction: &Instruction<T>) -> Option<Change<T>> {
+        self.history.as_ref()?;
+        let writes = instruction.written_argument()
+            .and_then(|a| a.address())
+            .and_then(|a| to_address(&a).ok().map(|index| (a, self.memory.read(index), self.memory.read(index))))
+            .into_iter()
+            .collect();
+        let outputs = match instruction {
+            Instruction::Output(source) => source.get(self.memory.as_ref()).ok().into_iter().collect(),
+            _ => vec![]
+        };
+        Some(Change { loc: self.loc.clone(), relative_base: self.relative_base.clone(), writes, inputs: vec![], outputs, queued: 0 })
+    }
+
+    /// Completes and records a change once its instruction has executed successfully, taking
+    /// the effects of an extension instruction from what its handler did.
+    pub(super) fn end_change(&mut self, instruction: &Instruction<T>, mut change: Change<T>, activity: Option<&Activity<T>>) {
+        if let Some(activity) = activity {
+            change.writes = activity.writes.clone();
+            change.inputs = activity.inputs.clone();
+            change.outputs = activity.outputs.clone();
+        } else {
+            for (address, _, new) in change.writes.iter_mut() {
+                *new = self.memory.read(to_address(address).unwrap_or(0));
+                if let Instruction::Input(_) = instruction {
+                    change.inputs.push(new.clone());
+                }
+            }
+        }
+        if let ComputerOutput::Queue(_) = self.output {
+            change.queued = change.outputs.len();
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.push(change);
+        }
+    }
+
+    /// Notes that the latest output has been taken from the output queue, so that undoing the
+    /// step that produced it leaves the queue alone.
+    pub(super) fn output_taken(&mut self) {
+        if let Some(change) = self.history.as_mut().and_then(|h| h.last_mut()) {
+            change.queued = change.queued.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::mpsc::{channel, sync_channel};
+    use super::super::RunStatus;
+
+    #[test]
+    fn test_step_back_restores_state() {
+        let mut computer = Computer::new(vec![109, 5, 21101, 2, 3, 0, 99], None, None);
+        computer.start_recording();
+        computer.run().unwrap();
+        assert_eq!(Ok(5), computer.read(5));
+        assert_eq!(3, computer.history().len());
+
+        assert_eq!(Some(Change { loc: 6, relative_base: 5, writes: vec![], inputs: vec![], outputs: vec![], queued: 0 }), computer.step_back());
+        assert!(computer.is_running());
+        assert_eq!(Some(Change { loc: 2, relative_base: 5, writes: vec![(5, 0, 5)], inputs: vec![], outputs: vec![], queued: 0 }), computer.step_back());
+        assert_eq!(Ok(0), computer.read(5));
+        computer.step_back().unwrap();
+        assert_eq!((0, 0, 0), (computer.loc(), computer.relative_base(), computer.steps()));
+        assert_eq!(None, computer.step_back());
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        let program = vec![3, 13, 3, 14, 1, 13, 14, 15, 4, 15, 1105, 1, 0, 0, 0, 0];
+        let mut computer = Computer::new(program, Some(ComputerInput::Queue(VecDeque::from(vec![1, 2, 3, 4]))), None);
+        computer.start_recording();
+        for _ in 0..10 {
+            computer.step().unwrap();
+        }
+        assert_eq!(vec![3, 7], Vec::from(computer.output()));
+
+        for _ in 0..6 {
+            computer.step_back().unwrap();
+        }
+        assert_eq!(vec![3], Vec::from(computer.output()));
+        for _ in 0..6 {
+            computer.step().unwrap();
+        }
+        assert_eq!(vec![3, 7], Vec::from(computer.output()));
+        assert!(computer.step().is_err());
+    }
+
+    #[test]
+    fn test_replay_channel_input() {
+        let (tx, rx) = channel();
+        let (out_tx, _out_rx) = sync_channel(10);
+        let mut computer = Computer::new(vec![3, 7, 4, 7, 1105, 1, 0, 0], Some(ComputerInput::Channel(rx)), Some(ComputerOutput::Channel(out_tx)));
+        computer.start_recording();
+        tx.send(8).unwrap();
+        computer.step().unwrap();
+        assert_eq!(Some(vec![8]), computer.step_back().map(|c| c.inputs));
+        assert_eq!(Ok(0), computer.read(7));
+        drop(tx);
+        computer.step().unwrap();
+        assert_eq!(Ok(8), computer.read(7));
+    }
+
+    #[test]
+    fn test_step_back_after_resume() {
+        let mut computer = Computer::new(vec![104, 7, 104, 8, 99], None, None);
+        computer.start_recording();
+        computer.step().unwrap();
+        assert_eq!(Ok(RunStatus::ProducedOutput(8)), computer.resume());
+        computer.step_back().unwrap();
+        assert_eq!(vec![7], Vec::from(computer.output()));
+        computer.step_back().unwrap();
+        assert!(computer.output().is_empty());
+    }
+
+    #[test]
+    fn test_stop_recording_discards_history() {
+        let mut computer = Computer::new(vec![1101, 1, 1, 0, 99], None, None);
+        computer.step().unwrap();
+        assert!(computer.history().is_empty());
+        computer.start_recording();
+        computer.step().unwrap();
+        computer.stop_recording();
+        assert_eq!(None, computer.step_back());
+    }
+}
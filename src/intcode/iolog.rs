@@ -0,0 +1,376 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, IntcodeError};
+use super::trace::{TraceEvent, Tracer};
+
+/// Whether a value was read or written by a machine.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Input => write!(f, "in"),
+            Direction::Output => write!(f, "out")
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in" => Ok(Direction::Input),
+            "out" => Ok(Direction::Output),
+            _ => Err(format!("Unknown direction '{}': expected in or out.", s))
+        }
+    }
+}
+
+/// A value passing into or out of one machine.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct IoEvent<T> {
+    /// The position of the event in the whole log, across all machines.
+    pub sequence: u64,
+    pub machine: String,
+    /// The position of the event among the inputs or outputs of its machine.
+    pub index: u64,
+    pub direction: Direction,
+    pub value: T,
+}
+
+/// A log of the values read and written by a group of machines, in the order they happened.
+///
+/// The log is written as text, one tab-separated event per line: sequence, machine, index,
+/// direction (`in` or `out`) and value. Lines starting with `#` are comments.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct IoLog<T> {
+    events: Vec<IoEvent<T>>,
+    /// The sequence number of the next event to be logged.
+    next_sequence: u64,
+    /// The index of the next event to be logged for each machine in each direction.
+    next_index: HashMap<(String, Direction), u64>,
+}
+
+impl<T> Default for IoLog<T> {
+    fn default() -> Self {
+        IoLog { events: vec![], next_sequence: 0, next_index: HashMap::new() }
+    }
+}
+
+impl<T: Clone> IoLog<T> {
+    pub fn new() -> Self {
+        IoLog::default()
+    }
+
+    pub fn events(&self) -> &[IoEvent<T>] {
+        &self.events
+    }
+
+    /// Adds an event for a machine, numbering it after the events already logged.
+    pub fn push(&mut self, machine: &str, direction: Direction, value: T) {
+        let next = self.next_index.entry((String::from(machine), direction)).or_insert(0);
+        let index = *next;
+        *next += 1;
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.events.push(IoEvent { sequence, machine: String::from(machine), index, direction, value });
+    }
+
+    /// Returns the names of the machines in the log, in order of their first event.
+    pub fn machines(&self) -> Vec<&str> {
+        self.events.iter().map(|e| e.machine.as_str()).unique().collect()
+    }
+
+    fn values(&self, machine: &str, direction: Direction) -> Vec<T> {
+        let mut events: Vec<&IoEvent<T>> = self.events.iter()
+            .filter(|e| e.machine == machine && e.direction == direction)
+            .collect();
+        events.sort_by_key(|e| e.index);
+        events.iter().map(|e| e.value.clone()).collect()
+    }
+
+    pub fn inputs(&self, machine: &str) -> Vec<T> {
+        self.values(machine, Direction::Input)
+    }
+
+    pub fn outputs(&self, machine: &str) -> Vec<T> {
+        self.values(machine, Direction::Output)
+    }
+}
+
+impl<T> IoLog<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Runs a program as the given machine, feeding it the recorded inputs from a queue and checking
+    /// that it produces the recorded outputs. The computer is returned for inspection.
+    ///
+    /// Because the inputs no longer depend on the timing of other machines, this reproduces the
+    /// machine's part of the recorded run exactly.
+    pub fn replay(&self, machine: &str, program: Vec<T>) -> Result<Computer<T>, ReplayError<T>> {
+        let input = ComputerInput::Queue(VecDeque::from(self.inputs(machine)));
+        let mut computer = Computer::new(program, Some(input), None);
+        let result = computer.run();
+
+        let expected = self.outputs(machine);
+        let actual = Vec::from(computer.output());
+        let divergence = (0..expected.len().max(actual.len()))
+            .find(|&i| expected.get(i) != actual.get(i));
+        if let Some(index) = divergence {
+            return Err(ReplayError::Diverged {
+                machine: String::from(machine),
+                index,
+                expected: expected.get(index).cloned(),
+                actual: actual.get(index).cloned(),
+            });
+        }
+        result.map_err(ReplayError::Intcode)?;
+        Ok(computer)
+    }
+}
+
+impl<T> IoLog<T> where T: Clone + FromStr + fmt::Display {
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "# sequence\tmachine\tindex\tdirection\tvalue")?;
+        for event in &self.events {
+            writeln!(writer, "{}\t{}\t{}\t{}\t{}", event.sequence, event.machine, event.index, event.direction, event.value)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: R) -> io::Result<Self> {
+        let invalid = |line: usize, message: String| io::Error::new(ErrorKind::InvalidData, format!("Line {}: {}", line, message));
+        let mut events = vec![];
+        for (number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (sequence, machine, index, direction, value) = match fields.as_slice() {
+                [s, m, i, d, v] => (s, m, i, d, v),
+                _ => return Err(invalid(number + 1, format!("Expected 5 tab-separated fields but found {}.", fields.len())))
+            };
+            events.push(IoEvent {
+                sequence: sequence.parse().map_err(|_| invalid(number + 1, format!("Invalid sequence number '{}'.", sequence)))?,
+                machine: String::from(*machine),
+                index: index.parse().map_err(|_| invalid(number + 1, format!("Invalid index '{}'.", index)))?,
+                direction: direction.parse().map_err(|e| invalid(number + 1, e))?,
+                value: value.parse().map_err(|_| invalid(number + 1, format!("Invalid value '{}'.", value)))?,
+            });
+        }
+        // Number new events after the highest ones loaded, as a filtered log may have gaps.
+        let next_sequence = events.iter().map(|e| e.sequence + 1).max().unwrap_or(0);
+        let mut next_index = HashMap::new();
+        for event in &events {
+            let next = next_index.entry((event.machine.clone(), event.direction)).or_insert(0);
+            *next = (*next).max(event.index + 1);
+        }
+        Ok(IoLog { events, next_sequence, next_index })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(File::create(path)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        IoLog::read_from(File::open(path)?)
+    }
+}
+
+/// The reason a machine could not be replayed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ReplayError<T> {
+    /// The program failed while being replayed.
+    Intcode(IntcodeError<T>),
+    /// The output at `index` differed from the recording. `None` means that there was no value.
+    Diverged { machine: String, index: usize, expected: Option<T>, actual: Option<T> },
+}
+
+impl<T: fmt::Display> fmt::Display for ReplayError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |value: &Option<T>| value.as_ref().map_or(String::from("nothing"), |v| v.to_string());
+        match self {
+            ReplayError::Intcode(e) => write!(f, "{}", e),
+            ReplayError::Diverged { machine, index, expected, actual } =>
+                write!(f, "Machine {} output {} at index {} but the recording has {}.", machine, describe(actual), index, describe(expected))
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for ReplayError<T> {}
+
+/// Records the I/O of any number of machines into a shared `IoLog`. Each machine is given its
+/// own tracer, and clones of the recorder share the same log.
+#[derive(Clone)]
+pub struct IoRecorder<T> {
+    log: Arc<Mutex<IoLog<T>>>,
+}
+
+impl<T> Default for IoRecorder<T> {
+    fn default() -> Self {
+        IoRecorder { log: Arc::new(Mutex::new(IoLog::default())) }
+    }
+}
+
+impl<T: Clone + Send + 'static> IoRecorder<T> {
+    pub fn new() -> Self {
+        IoRecorder::default()
+    }
+
+    /// Makes a tracer that logs the I/O of the computer it is given to under the name of a
+    /// machine. The name must not contain tabs or newlines.
+    pub fn tracer(&self, machine: &str) -> Box<dyn Tracer<T>> {
+        Box::new(MachineRecorder { log: self.log.clone(), machine: String::from(machine) })
+    }
+
+    /// Returns a copy of everything logged so far.
+    pub fn log(&self) -> IoLog<T> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+struct MachineRecorder<T> {
+    log: Arc<Mutex<IoLog<T>>>,
+    machine: String,
+}
+
+impl<T: Clone + Send> Tracer<T> for MachineRecorder<T> {
+    fn record(&mut self, event: &TraceEvent<T>) {
+        let mut log = self.log.lock().unwrap();
+        for value in &event.inputs {
+            log.push(&self.machine, Direction::Input, value.clone());
+        }
+        for value in &event.outputs {
+            log.push(&self.machine, Direction::Output, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+    use std::thread;
+    use crate::intcode::ComputerOutput;
+    use crate::intcode::assembler::assemble;
+
+    /// Outputs double each input, halting after it reads zero.
+    fn doubler() -> Vec<i64> {
+        assemble("
+            loop:   in [x]
+                    mul [x], #2, [x]
+                    out [x]
+                    jt [x], #loop
+                    hlt
+            x:      .data 0").unwrap()
+    }
+
+    #[test]
+    fn test_records_sequence_numbers() {
+        let recorder = IoRecorder::new();
+        let mut log = recorder.log();
+        assert!(log.events().is_empty());
+
+        let mut computer = Computer::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0], Some(ComputerInput::Queue(VecDeque::from(vec![5, 6]))), None);
+        computer.set_tracer(recorder.tracer("A"));
+        computer.run().unwrap();
+        log = recorder.log();
+        assert_eq!(vec![5, 6], log.inputs("A"));
+        assert_eq!(vec![5, 6], log.outputs("A"));
+        assert_eq!(IoEvent { sequence: 3, machine: String::from("A"), index: 1, direction: Direction::Output, value: 6 }, log.events()[3]);
+    }
+
+    #[test]
+    fn test_record_threaded_and_replay() {
+        let recorder = IoRecorder::new();
+        let (to_a, a_in) = sync_channel(10);
+        let (a_out, b_in) = sync_channel(10);
+        let (b_out, results) = sync_channel(10);
+
+        let program = doubler();
+        let launch = |name: &str, input, output| {
+            let (program, tracer) = (program.clone(), recorder.tracer(name));
+            thread::spawn(move || {
+                let mut computer = Computer::new(program, Some(ComputerInput::Channel(input)), Some(ComputerOutput::Channel(output)));
+                computer.set_tracer(tracer);
+                computer.run()
+            })
+        };
+        let a = launch("A", a_in, a_out);
+        let b = launch("B", b_in, b_out);
+        for value in &[10, 20, 0] {
+            to_a.send(*value).unwrap();
+        }
+        a.join().unwrap().unwrap();
+        b.join().unwrap().unwrap();
+        assert_eq!(vec![40, 80, 0], results.iter().collect::<Vec<i64>>());
+
+        let mut text = vec![];
+        recorder.log().write_to(&mut text).unwrap();
+        let log = IoLog::read_from(text.as_slice()).unwrap();
+        assert_eq!(recorder.log(), log);
+        assert_eq!(vec!["A", "B"], log.machines());
+        assert_eq!(vec![20, 40, 0], log.inputs("B"));
+        assert_eq!(vec![40, 80, 0], log.outputs("B"));
+        for machine in log.machines() {
+            let computer = log.replay(machine, program.clone()).unwrap();
+            assert!(!computer.is_running());
+        }
+        assert!(log.replay("A", vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]).is_err());
+    }
+
+    #[test]
+    fn test_replay_detects_divergence() {
+        let mut log = IoLog::new();
+        log.push("M", Direction::Input, 4);
+        log.push("M", Direction::Output, 4);
+        log.push("M", Direction::Input, 7);
+        log.push("M", Direction::Output, 9);
+        let program = vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0];
+
+        assert_eq!(Err(ReplayError::Diverged { machine: String::from("M"), index: 1, expected: Some(9), actual: Some(7) }),
+                   log.replay("M", program.clone()).map(|_| ()));
+        let mut log = IoLog::new();
+        for (direction, value) in &[(Direction::Input, 4), (Direction::Output, 4), (Direction::Input, 7), (Direction::Output, 7)] {
+            log.push("M", *direction, *value);
+        }
+        assert!(log.replay("M", program).is_ok());
+    }
+
+    #[test]
+    fn test_push_after_read_continues_numbering() {
+        let mut log = IoLog::<i64>::read_from("0\tA\t0\tin\t4\n1\tA\t0\tout\t5\n2\tA\t1\tin\t6\n".as_bytes()).unwrap();
+        log.push("A", Direction::Input, 7);
+        log.push("B", Direction::Input, 8);
+        assert_eq!(IoEvent { sequence: 3, machine: String::from("A"), index: 2, direction: Direction::Input, value: 7 }, log.events()[3]);
+        assert_eq!(0, log.events()[4].index);
+    }
+
+    #[test]
+    fn test_push_after_read_with_gaps() {
+        let mut log = IoLog::<i64>::read_from("3\tA\t2\tin\t4\n7\tA\t5\tout\t5\n".as_bytes()).unwrap();
+        log.push("A", Direction::Input, 6);
+        log.push("A", Direction::Output, 7);
+        assert_eq!(IoEvent { sequence: 8, machine: String::from("A"), index: 3, direction: Direction::Input, value: 6 }, log.events()[2]);
+        assert_eq!((9, 6), (log.events()[3].sequence, log.events()[3].index));
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(IoLog::<i64>::read_from("0\tA\t0\tin\n".as_bytes()).is_err());
+        assert!(IoLog::<i64>::read_from("0\tA\t0\tsideways\t4\n".as_bytes()).is_err());
+        assert_eq!(1, IoLog::<i64>::read_from("# comment\n0\tA\t0\tin\t4\n".as_bytes()).unwrap().events().len());
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use num::Zero;
+
+/// Addresses below this limit are stored contiguously by `DenseMemory`. Anything above it is
+/// assumed to be a stray write and is kept in a sparse map instead, so that a single huge address
+/// doesn't allocate gigabytes of zeroes.
+const DENSE_LIMIT: usize = 1 << 20;
+
+/// Storage for the memory of a `Computer`.
+///
+/// Addresses are always non-negative by the time they reach the memory: the computer rejects
+/// negative addresses before reading or writing. Memory must be `Send` so that a computer can be
+/// run on another thread.
+pub trait Memory<T>: Send {
+    /// Reads the value at an address. Memory that has never been written reads as zero.
+    fn read(&self, address: usize) -> T;
+
+    fn write(&mut self, address: usize, value: T);
+
+    /// Returns the number of cells that are currently backed by storage.
+    fn footprint(&self) -> usize;
+
+    /// Returns every stored cell in address order.
+    fn cells(&self) -> Vec<(usize, T)>;
+
+    fn boxed_clone(&self) -> Box<dyn Memory<T>>;
+}
+
+/// The available memory implementations.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MemoryKind {
+    /// A growable vector, falling back to a map for very large addresses.
+    Dense,
+    /// A hash map holding only the cells that have been written.
+    Sparse,
+}
+
+impl MemoryKind {
+    /// Creates a memory of this kind holding the given program from address zero.
+    pub fn load<T: Clone + Zero + Send + 'static>(self, program: Vec<T>) -> Box<dyn Memory<T>> {
+        match self {
+            MemoryKind::Dense => Box::new(DenseMemory::new(program)),
+            MemoryKind::Sparse => Box::new(program.into_iter().enumerate().collect::<HashMap<usize, T>>())
+        }
+    }
+}
+
+impl<T: Clone + Zero + Send + 'static> Memory<T> for HashMap<usize, T> {
+    fn read(&self, address: usize) -> T {
+        self.get(&address).cloned().unwrap_or_else(T::zero)
+    }
+
+    fn write(&mut self, address: usize, value: T) {
+        self.insert(address, value);
+    }
+
+    fn footprint(&self) -> usize {
+        self.len()
+    }
+
+    fn cells(&self) -> Vec<(usize, T)> {
+        let mut cells: Vec<(usize, T)> = self.iter().map(|(&a, v)| (a, v.clone())).collect();
+        cells.sort_by_key(|&(a, _)| a);
+        cells
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Memory<T>> {
+        Box::new(self.clone())
+    }
+}
+
+/// Memory stored in a vector that grows to cover the highest address written, with a sparse
+/// fallback for addresses beyond `DENSE_LIMIT`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DenseMemory<T> {
+    cells: Vec<T>,
+    overflow: HashMap<usize, T>,
+}
+
+impl<T: Clone + Zero> DenseMemory<T> {
+    pub fn new(program: Vec<T>) -> Self {
+        DenseMemory { cells: program, overflow: HashMap::new() }
+    }
+}
+
+impl<T: Clone + Zero + Send + 'static> Memory<T> for DenseMemory<T> {
+    fn read(&self, address: usize) -> T {
+        match self.cells.get(address) {
+            Some(value) => value.clone(),
+            None => self.overflow.read(address)
+        }
+    }
+
+    fn write(&mut self, address: usize, value: T) {
+        if address < self.cells.len() {
+            self.cells[address] = value;
+        } else if address < DENSE_LIMIT {
+            self.cells.resize(address + 1, T::zero());
+            self.cells[address] = value;
+        } else {
+            self.overflow.insert(address, value);
+        }
+    }
+
+    fn footprint(&self) -> usize {
+        self.cells.len() + self.overflow.len()
+    }
+
+    fn cells(&self) -> Vec<(usize, T)> {
+        let mut cells: Vec<(usize, T)> = self.cells.iter().cloned().enumerate().collect();
+        cells.extend(self.overflow.cells());
+        cells
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Memory<T>> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dense_reads_zero_beyond_program() {
+        let memory = MemoryKind::Dense.load(vec![1, 2, 3]);
+        assert_eq!(2, memory.read(1));
+        assert_eq!(0, memory.read(100));
+        assert_eq!(3, memory.footprint());
+    }
+
+    #[test]
+    fn test_dense_grows() {
+        let mut memory = DenseMemory::new(vec![1, 2]);
+        memory.write(5, 7);
+        assert_eq!(vec![(0, 1), (1, 2), (2, 0), (3, 0), (4, 0), (5, 7)], memory.cells());
+    }
+
+    #[test]
+    fn test_dense_overflow_is_sparse() {
+        let mut memory = DenseMemory::new(vec![1i64]);
+        memory.write(DENSE_LIMIT * 4, 9);
+        assert_eq!(9, memory.read(DENSE_LIMIT * 4));
+        assert_eq!(0, memory.read(DENSE_LIMIT * 4 + 1));
+        assert_eq!(2, memory.footprint());
+        assert_eq!(vec![(0, 1), (DENSE_LIMIT * 4, 9)], memory.cells());
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut memory = MemoryKind::Sparse.load(vec![4, 5]);
+        memory.write(1000, 6);
+        assert_eq!(0, memory.read(999));
+        assert_eq!(vec![(0, 4), (1, 5), (1000, 6)], memory.cells());
+    }
+
+    #[test]
+    fn test_boxed_clone_is_independent() {
+        let mut memory = MemoryKind::Dense.load(vec![1, 2]);
+        let copy = memory.boxed_clone();
+        memory.write(0, 10);
+        assert_eq!(1, copy.read(0));
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, IntcodeError, RunStatus};
+
+/// A packet of two values sent to the computer at `destination`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Packet<T> {
+    pub destination: T,
+    pub x: T,
+    pub y: T,
+}
+
+impl<T: fmt::Display> fmt::Display for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} <- ({}, {})", self.destination, self.x, self.y)
+    }
+}
+
+/// Something that happened while running the network.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Event<T> {
+    /// The computer at `source` sent a packet.
+    Sent { source: usize, packet: Packet<T> },
+    /// The network was idle, so the NAT sent its last packet to address 0.
+    Woke(Packet<T>),
+    /// The network was idle and there was nothing to wake it.
+    Idle,
+}
+
+/// The reason a network stopped.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum NetworkError<T> {
+    /// A computer failed.
+    Intcode { address: usize, error: IntcodeError<T> },
+    /// The network went idle with nothing to wake it.
+    Idle,
+}
+
+impl<T: fmt::Display> fmt::Display for NetworkError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Intcode { address, error } => write!(f, "Computer {} failed: {}", address, error),
+            NetworkError::Idle => write!(f, "The network is idle.")
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for NetworkError<T> {}
+
+/// The NAT, which watches for the network going idle.
+struct Nat<T> {
+    address: T,
+    last: Option<Packet<T>>,
+}
+
+/// A network of computers running the same program, which exchange packets by address.
+///
+/// Each computer is given its address as its first input. After that, it sends a packet by
+/// outputting the destination address followed by X and Y, and receives packets as X then Y
+/// inputs, or a single -1 when there are none waiting.
+///
+/// The computers are run in turn on the current thread, each until it asks for input again. A
+/// round in which every computer found no packets waiting and none were sent leaves the network
+/// idle. If a NAT has been added, it then sends the last packet it received to address 0.
+pub struct Network<T: Signed + Integer> {
+    computers: Vec<Computer<T>>,
+    queues: Vec<VecDeque<(T, T)>>,
+    /// Output from each computer that doesn't yet make up a whole packet.
+    partial: Vec<Vec<T>>,
+    nat: Option<Nat<T>>,
+}
+
+impl<T> Network<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Boots `size` computers running `program`, with addresses 0 to `size - 1`.
+    pub fn new(program: &[T], size: usize) -> Self {
+        let computers = (0..size)
+            .map(|address| {
+                let input = VecDeque::from(vec![T::from_usize(address).expect("Network address out of range.")]);
+                Computer::new(program.to_vec(), Some(ComputerInput::Queue(input)), None)
+            })
+            .collect();
+        Network { computers, queues: vec![VecDeque::new(); size], partial: vec![vec![]; size], nat: None }
+    }
+
+    /// Adds a NAT that receives the packets sent to `address`.
+    pub fn with_nat(mut self, address: T) -> Self {
+        self.nat = Some(Nat { address, last: None });
+        self
+    }
+
+    /// Gives access to the computers, for example to set tracers or limits before running.
+    pub fn computers_mut(&mut self) -> &mut [Computer<T>] {
+        &mut self.computers
+    }
+
+    pub fn computers(&self) -> &[Computer<T>] {
+        &self.computers
+    }
+
+    /// Returns the last packet received by the NAT.
+    pub fn nat_packet(&self) -> Option<Packet<T>> {
+        self.nat.as_ref().and_then(|n| n.last.clone())
+    }
+
+    /// Delivers a packet as if it had been sent by one of the computers. Packets for addresses
+    /// that aren't on the network are dropped.
+    pub fn send(&mut self, packet: Packet<T>) {
+        if let Some(nat) = self.nat.as_mut().filter(|n| n.address == packet.destination) {
+            nat.last = Some(packet);
+        } else if let Some(queue) = packet.destination.to_usize().and_then(|a| self.queues.get_mut(a)) {
+            queue.push_back((packet.x, packet.y));
+        }
+    }
+
+    /// Gives each computer the packets waiting for it and runs it until it wants more, returning
+    /// what happened in the order it happened.
+    pub fn run_round(&mut self) -> Result<Vec<Event<T>>, NetworkError<T>> {
+        let mut events = vec![];
+        let mut idle = true;
+        for address in 0..self.computers.len() {
+            if !self.computers[address].is_running() {
+                continue;
+            }
+
+            let computer = &mut self.computers[address];
+            if self.queues[address].is_empty() {
+                computer.push_input(-T::one()).expect("Network computers read from queues.");
+            }
+            for (x, y) in self.queues[address].drain(..) {
+                idle = false;
+                computer.push_input(x).expect("Network computers read from queues.");
+                computer.push_input(y).expect("Network computers read from queues.");
+            }
+
+            while let RunStatus::ProducedOutput(value) = self.computers[address].resume().map_err(|error| NetworkError::Intcode { address, error })? {
+                self.partial[address].push(value);
+                if self.partial[address].len() == 3 {
+                    let mut values = std::mem::take(&mut self.partial[address]).into_iter();
+                    let (destination, x, y) = (values.next().unwrap(), values.next().unwrap(), values.next().unwrap());
+                    let packet = Packet { destination, x, y };
+                    idle = false;
+                    events.push(Event::Sent { source: address, packet: packet.clone() });
+                    self.send(packet);
+                }
+            }
+        }
+
+        if idle {
+            match self.nat_packet() {
+                Some(packet) => {
+                    let packet = Packet { destination: T::zero(), ..packet };
+                    self.send(packet.clone());
+                    events.push(Event::Woke(packet));
+                },
+                None => events.push(Event::Idle)
+            }
+        }
+        Ok(events)
+    }
+
+    /// Runs rounds until `stop` returns true for an event, and returns that event.
+    pub fn run_until<F: FnMut(&Event<T>) -> bool>(&mut self, mut stop: F) -> Result<Event<T>, NetworkError<T>> {
+        loop {
+            for event in self.run_round()? {
+                if stop(&event) {
+                    return Ok(event);
+                }
+                if event == Event::Idle {
+                    return Err(NetworkError::Idle);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    /// Passes each packet received on to the next address, adding one to Y.
+    const RELAY: &str = "
+                in [address]
+                add [address], #1, [next]
+        loop:   in [x]
+                eq [x], #-1, [empty]
+                jt [empty], #loop
+                in [y]
+                add [y], #1, [y]
+                out [next]
+                out [x]
+                out [y]
+                jt #1, #loop
+        address: .data 0
+        next:   .data 0
+        x:      .data 0
+        y:      .data 0
+        empty:  .data 0";
+
+    fn relay(size: usize) -> Network<i64> {
+        Network::new(&assemble::<i64>(RELAY).unwrap(), size)
+    }
+
+    #[test]
+    fn test_packets_are_routed() {
+        let mut network = relay(3);
+        network.send(Packet { destination: 0, x: 7, y: 10 });
+        let event = network.run_until(|e| matches!(e, Event::Sent { packet, .. } if packet.destination == 3));
+        assert_eq!(Ok(Event::Sent { source: 2, packet: Packet { destination: 3, x: 7, y: 13 } }), event);
+    }
+
+    #[test]
+    fn test_round_order() {
+        let mut network = relay(2);
+        network.send(Packet { destination: 1, x: 1, y: 1 });
+        network.send(Packet { destination: 0, x: 2, y: 2 });
+        assert_eq!(Ok(vec![
+            Event::Sent { source: 0, packet: Packet { destination: 1, x: 2, y: 3 } },
+            Event::Sent { source: 1, packet: Packet { destination: 2, x: 1, y: 2 } },
+            Event::Sent { source: 1, packet: Packet { destination: 2, x: 2, y: 4 } },
+        ]), network.run_round());
+        assert_eq!(Ok(vec![Event::Idle]), network.run_round());
+    }
+
+    #[test]
+    fn test_idle_without_nat() {
+        let mut network = relay(2);
+        assert_eq!(Err(NetworkError::Idle), network.run_until(|_| false));
+    }
+
+    #[test]
+    fn test_nat_wakes_idle_network() {
+        let mut network = relay(3).with_nat(3);
+        network.send(Packet { destination: 0, x: 7, y: 10 });
+        let mut woken = vec![];
+        network.run_until(|e| {
+            if let Event::Woke(packet) = e {
+                woken.push(packet.y);
+            }
+            woken.len() == 3
+        }).unwrap();
+        assert_eq!(vec![13, 16, 19], woken);
+        assert_eq!(Some(Packet { destination: 3, x: 7, y: 19 }), network.nat_packet());
+    }
+
+    #[test]
+    fn test_error_reports_address() {
+        let mut network = Network::new(&[3, 7, 1005, 7, 8, 99, 0, 0, 0], 2);
+        assert_eq!(Err(NetworkError::Intcode { address: 1, error: IntcodeError::UnknownOpcode { address: 8, instruction: 0 } }),
+                   network.run_round());
+    }
+}
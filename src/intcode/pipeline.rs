@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, IntcodeError, RunStatus};
+
+/// How the computers in a pipeline are connected.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Topology {
+    /// Each computer's output is the next computer's input.
+    Chain,
+    /// As for a chain, but the last computer's output is also fed back to the first.
+    Ring,
+}
+
+/// The reason a pipeline could not run to completion.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum PipelineError<T> {
+    /// A computer failed.
+    Intcode { node: usize, error: IntcodeError<T> },
+    /// The computers still running are all waiting for input that will never arrive.
+    Deadlock { waiting: Vec<usize> },
+}
+
+impl<T: fmt::Display> fmt::Display for PipelineError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Intcode { node, error } => write!(f, "Computer {} failed: {}", node, error),
+            PipelineError::Deadlock { waiting } =>
+                write!(f, "Deadlock: computers {} are waiting for input.", waiting.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", "))
+        }
+    }
+}
+
+impl<T: fmt::Debug + fmt::Display> Error for PipelineError<T> {}
+
+/// A group of computers running the same program, each passing its output to the next, such as
+/// the amplifiers of Day 7.
+///
+/// The computers are run in turn on the current thread, each until it needs input that hasn't
+/// been produced yet, so no threads or channels are needed and the result doesn't depend on timing.
+pub struct Pipeline<T: Signed + Integer> {
+    computers: Vec<Computer<T>>,
+    topology: Topology,
+}
+
+impl<T> Pipeline<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Creates a pipeline with one computer per entry in `initial_inputs`, each starting with
+    /// those values in its input queue.
+    pub fn new(program: &[T], initial_inputs: Vec<Vec<T>>, topology: Topology) -> Self {
+        let computers = initial_inputs.into_iter()
+            .map(|input| Computer::new(program.to_vec(), Some(ComputerInput::Queue(VecDeque::from(input))), None))
+            .collect();
+        Pipeline { computers, topology }
+    }
+
+    /// Creates a pipeline with one computer per phase setting, each given its phase as its first
+    /// input.
+    pub fn with_phases(program: &[T], phases: &[T], topology: Topology) -> Self {
+        Pipeline::new(program, phases.iter().map(|p| vec![p.clone()]).collect(), topology)
+    }
+
+    /// Gives access to the computers, for example to set tracers or limits before running.
+    pub fn computers_mut(&mut self) -> &mut [Computer<T>] {
+        &mut self.computers
+    }
+
+    pub fn computers(&self) -> &[Computer<T>] {
+        &self.computers
+    }
+
+    /// Sends `input` to the first computer and runs the pipeline until every computer has
+    /// halted, returning everything output by the last computer. In a ring those values are also
+    /// passed back to the first computer, so the final result is the last value returned.
+    pub fn run(&mut self, input: &[T]) -> Result<Vec<T>, PipelineError<T>> {
+        let count = self.computers.len();
+        if count == 0 {
+            return Ok(input.to_vec());
+        }
+        for value in input {
+            self.feed(0, value.clone());
+        }
+
+        let mut outputs = vec![];
+        loop {
+            let mut produced = false;
+            for node in 0..count {
+                loop {
+                    let status = self.computers[node].resume().map_err(|error| PipelineError::Intcode { node, error })?;
+                    match status {
+                        RunStatus::ProducedOutput(value) => {
+                            produced = true;
+                            if node + 1 < count {
+                                self.feed(node + 1, value);
+                            } else {
+                                if self.topology == Topology::Ring {
+                                    self.feed(0, value.clone());
+                                }
+                                outputs.push(value);
+                            }
+                        },
+                        RunStatus::NeedsInput | RunStatus::Halted => break
+                    }
+                }
+            }
+
+            let waiting: Vec<usize> = (0..count).filter(|&n| self.computers[n].is_running()).collect();
+            if waiting.is_empty() {
+                return Ok(outputs);
+            }
+            if !produced {
+                return Err(PipelineError::Deadlock { waiting });
+            }
+        }
+    }
+
+    fn feed(&mut self, node: usize, value: T) {
+        self.computers[node].push_input(value).expect("Pipeline computers read from queues.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain() {
+        let program = vec![3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+        let mut pipeline = Pipeline::with_phases(&program, &[4, 3, 2, 1, 0], Topology::Chain);
+        assert_eq!(Ok(vec![43210]), pipeline.run(&[0]));
+        assert!(pipeline.computers().iter().all(|c| !c.is_running()));
+    }
+
+    #[test]
+    fn test_ring() {
+        let program = vec![3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5];
+        let mut pipeline = Pipeline::with_phases(&program, &[9, 8, 7, 6, 5], Topology::Ring);
+        assert_eq!(Some(&139629729), pipeline.run(&[0]).unwrap().last());
+    }
+
+    #[test]
+    fn test_per_node_inputs() {
+        // Adds its two inputs and outputs the sum.
+        let program = vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+        let mut pipeline = Pipeline::new(&program, vec![vec![], vec![100]], Topology::Chain);
+        assert_eq!(Ok(vec![107]), pipeline.run(&[3, 4]));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        // Each computer wants two inputs before producing anything.
+        let mut pipeline = Pipeline::with_phases(&[3, 7, 3, 7, 4, 7, 99, 0], &[1, 2], Topology::Ring);
+        assert_eq!(Err(PipelineError::Deadlock { waiting: vec![0, 1] }), pipeline.run(&[]));
+    }
+
+    #[test]
+    fn test_error_reports_node() {
+        // Halts given 0, but jumps to the invalid instruction at 7 given anything else.
+        let mut pipeline = Pipeline::with_phases(&[3, 6, 1005, 6, 7, 99, 0, 0], &[0, 1], Topology::Chain);
+        assert_eq!(Err(PipelineError::Intcode { node: 1, error: IntcodeError::UnknownOpcode { address: 7, instruction: 0 } }),
+                   pipeline.run(&[]));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, Instruction, IntcodeError};
+use super::disassembler::{self, Entry};
+
+/// Execution statistics gathered by running a program under `profile`.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Profile<T: Ord> {
+    pub steps: u64,
+    /// The number of times the instruction at each address was executed.
+    pub by_address: BTreeMap<T, u64>,
+    /// The number of times each kind of instruction was executed, by mnemonic.
+    pub by_opcode: BTreeMap<&'static str, u64>,
+    pub inputs: u64,
+    pub outputs: u64,
+    /// The number of memory cells in use when the run finished.
+    pub memory_footprint: usize,
+}
+
+/// Runs a computer until it halts, counting every instruction executed.
+///
+/// If the run fails, the error is returned along with the statistics gathered up to that point.
+pub fn profile<T>(computer: &mut Computer<T>) -> Result<Profile<T>, (IntcodeError<T>, Profile<T>)>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let mut profile = Profile {
+        steps: 0,
+        by_address: BTreeMap::new(),
+        by_opcode: BTreeMap::new(),
+        inputs: 0,
+        outputs: 0,
+        memory_footprint: 0,
+    };
+
+    while computer.is_running() {
+        let address = computer.loc();
+        let result = computer.current_instruction().and_then(|instruction| {
+            computer.step()?;
+            Ok(instruction)
+        });
+        let instruction = match result {
+            Ok(i) => i,
+            Err(e) => {
+                profile.memory_footprint = computer.memory_footprint();
+                return Err((e, profile));
+            }
+        };
+
+        profile.steps += 1;
+        *profile.by_address.entry(address).or_insert(0) += 1;
+        *profile.by_opcode.entry(instruction.mnemonic()).or_insert(0) += 1;
+        match instruction {
+            Instruction::Input(_) => profile.inputs += 1,
+            Instruction::Output(_) => profile.outputs += 1,
+            _ => ()
+        }
+    }
+
+    profile.memory_footprint = computer.memory_footprint();
+    Ok(profile)
+}
+
+impl<T> Profile<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Formats a report of the run: totals, counts per opcode and the `limit` most executed
+    /// addresses. If the program image is given, each hotspot is annotated with its disassembly.
+    pub fn report(&self, program: Option<&[T]>, limit: usize) -> String {
+        let mut lines = vec![
+            format!("Steps: {}", self.steps),
+            format!("Inputs: {}", self.inputs),
+            format!("Outputs: {}", self.outputs),
+            format!("Memory footprint: {} cells", self.memory_footprint),
+            String::new(),
+            String::from("Opcode        Count      %"),
+        ];
+        let percent = |count: u64| 100.0 * count as f64 / self.steps.max(1) as f64;
+        for (mnemonic, count) in self.by_opcode.iter().sorted_by_key(|(m, &c)| (std::cmp::Reverse(c), *m)) {
+            lines.push(format!("{:<6} {:>12} {:>6.2}", mnemonic, count, percent(*count)));
+        }
+
+        let listing: HashMap<usize, String> = program.map(|p| disassembler::disassemble(p).into_iter()
+            .filter_map(|line| match line.entry {
+                Entry::Instruction(i) => Some((line.address, i.to_string())),
+                Entry::Data(_) => None
+            })
+            .collect())
+            .unwrap_or_default();
+
+        lines.push(String::new());
+        lines.push(String::from("Address        Count      %"));
+        let hotspots = self.by_address.iter().sorted_by_key(|&(a, &c)| (std::cmp::Reverse(c), a)).take(limit);
+        for (address, count) in hotspots {
+            let mut line = format!("{:>7} {:>12} {:>6.2}", address, count, percent(*count));
+            if program.is_some() {
+                let text = address.to_usize().and_then(|a| listing.get(&a));
+                line += &format!("  {}", text.map_or("(not in static disassembly)", |t| t.as_str()));
+            }
+            lines.push(line);
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    fn countdown() -> Vec<i64> {
+        assemble("
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      .data 0").unwrap()
+    }
+
+    #[test]
+    fn test_profile_counts() {
+        let mut computer = Computer::new(countdown(), None, None);
+        computer.push_input(3).unwrap();
+        let profile = profile(&mut computer).unwrap();
+        assert_eq!(1 + 3 * 3 + 1, profile.steps);
+        assert_eq!(Some(&3), profile.by_address.get(&2));
+        assert_eq!(Some(&1), profile.by_address.get(&0));
+        assert_eq!(Some(&3), profile.by_opcode.get("add"));
+        assert_eq!(1, profile.inputs);
+        assert_eq!(3, profile.outputs);
+        assert_eq!(13, profile.memory_footprint);
+    }
+
+    #[test]
+    fn test_profile_error_keeps_statistics() {
+        let mut computer = Computer::new(countdown(), None, None);
+        let (error, profile) = profile(&mut computer).unwrap_err();
+        assert_eq!(IntcodeError::InputExhausted { address: 0, instruction: 3 }, error);
+        assert_eq!(0, profile.steps);
+    }
+
+    #[test]
+    fn test_report() {
+        let program = countdown();
+        let mut computer = Computer::new(program.clone(), None, None);
+        computer.push_input(2).unwrap();
+        let report = profile(&mut computer).unwrap().report(Some(&program), 2);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!("Steps: 8", lines[0]);
+        assert_eq!(format!("{:<6} {:>12} {:>6.2}", "add", 2, 25.0), lines[6]);
+        let hotspots = &lines[lines.len() - 2..];
+        assert_eq!(format!("{:>7} {:>12} {:>6.2}  out [12]", 2, 2, 25.0), hotspots[0]);
+        assert_eq!(format!("{:>7} {:>12} {:>6.2}  add [12], #-1, [12]", 4, 2, 25.0), hotspots[1]);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Computer, ComputerInput, ComputerOutput};
+use super::memory::{Memory, MemoryKind};
+
+/// A copy of the complete state of a `Computer`, which can be restored later or written to a file.
+///
+/// Only queue-backed I/O can be captured: a computer reading from a channel is recorded with an
+/// empty input queue, and one writing to a channel is recorded with the outputs that could not be
+/// sent.
+pub struct Snapshot<T> {
+    memory: Box<dyn Memory<T>>,
+    loc: T,
+    relative_base: T,
+    running: bool,
+    steps: u64,
+    exit_code: Option<T>,
+    input: VecDeque<T>,
+    output: VecDeque<T>,
+}
+
+impl<T> Clone for Snapshot<T> where T: Clone {
+    fn clone(&self) -> Self {
+        Snapshot {
+            memory: self.memory.boxed_clone(),
+            loc: self.loc.clone(),
+            relative_base: self.relative_base.clone(),
+            running: self.running,
+            steps: self.steps,
+            exit_code: self.exit_code.clone(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+        }
+    }
+}
+
+impl<T> PartialEq for Snapshot<T> where T: PartialEq {
+    fn eq(&self, other: &Self) -> bool {
+        self.memory.cells() == other.memory.cells()
+            && self.loc == other.loc
+            && self.relative_base == other.relative_base
+            && self.running == other.running
+            && self.steps == other.steps
+            && self.exit_code == other.exit_code
+            && self.input == other.input
+            && self.output == other.output
+    }
+}
+
+impl<T> fmt::Debug for Snapshot<T> where T: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Memory: {:?} Location: {:?} Relative base: {:?} Running: {:?} Steps: {:?} Exit code: {:?} Input: {:?} Output: {:?}",
+               self.memory.cells(), self.loc, self.relative_base, self.running, self.steps, self.exit_code, self.input, self.output)
+    }
+}
+
+impl<T> Computer<T> where T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static {
+    /// Captures the current state of the computer. This copies the whole of memory, which for
+    /// dense memory is a single vector copy.
+    pub fn snapshot(&self) -> Snapshot<T> {
+        Snapshot {
+            memory: self.memory.boxed_clone(),
+            loc: self.loc.clone(),
+            relative_base: self.relative_base.clone(),
+            running: self.running,
+            steps: self.steps,
+            exit_code: self.exit_code.clone(),
+            input: match &self.input {
+                ComputerInput::Queue(q) => q.clone(),
+                ComputerInput::Channel(_) => VecDeque::new()
+            },
+            output: self.output(),
+        }
+    }
+
+    /// Returns the computer to the state held in a snapshot. Channels used for I/O are kept, so
+    /// only queue-backed input and output are replaced. The step count and exit code go back to
+    /// those of the snapshot, so steps taken since don't count towards a step limit. Any recorded
+    /// history is discarded.
+    pub fn restore(&mut self, snapshot: &Snapshot<T>) {
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        self.replay.clear();
+        self.memory = snapshot.memory.boxed_clone();
+        self.decoded.clear();
+        self.loc = snapshot.loc.clone();
+        self.relative_base = snapshot.relative_base.clone();
+        self.running = snapshot.running;
+        self.steps = snapshot.steps;
+        self.exit_code = snapshot.exit_code.clone();
+        if let ComputerInput::Queue(q) = &mut self.input {
+            *q = snapshot.input.clone();
+        }
+        match &mut self.output {
+            ComputerOutput::Queue(q) => *q = snapshot.output.clone(),
+            ComputerOutput::Channel(_) => self.alt_output = snapshot.output.clone()
+        }
+    }
+
+    /// Creates a new queue-backed computer from a snapshot.
+    pub fn from_snapshot(snapshot: &Snapshot<T>) -> Self {
+        let mut computer = Computer::new(vec![], None, None);
+        computer.restore(snapshot);
+        computer
+    }
+}
+
+impl<T> Snapshot<T> where T: Integer + Clone + FromStr + fmt::Display + Send + 'static {
+    /// Writes the snapshot in a line-based text format, one `key value` pair per line.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "loc {}", self.loc)?;
+        writeln!(writer, "relative_base {}", self.relative_base)?;
+        writeln!(writer, "running {}", self.running)?;
+        writeln!(writer, "steps {}", self.steps)?;
+        writeln!(writer, "exit_code {}", self.exit_code.as_ref().map_or(String::new(), |code| code.to_string()))?;
+        writeln!(writer, "input {}", self.input.iter().join(","))?;
+        writeln!(writer, "output {}", self.output.iter().join(","))?;
+        let mut cells = self.memory.cells().into_iter().map(|(address, value)| format!("{}:{}", address, value));
+        writeln!(writer, "memory {}", cells.join(","))
+    }
+
+    /// Reads a snapshot previously written by `write_to`. Snapshots written before the step count
+    /// and exit code were recorded are read as having taken no steps and set no exit code.
+    pub fn read_from<R: Read>(reader: R) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+        let parse = |text: &str| text.parse::<T>().map_err(|_| invalid(format!("Invalid value '{}'.", text)));
+        let parse_list = |text: &str| -> io::Result<VecDeque<T>> {
+            text.split(',').filter(|s| !s.is_empty()).map(parse).collect()
+        };
+
+        let mut fields = HashMap::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap_or("");
+            if !key.is_empty() {
+                fields.insert(String::from(key), String::from(parts.next().unwrap_or("").trim()));
+            }
+        }
+        let field = |key: &str| fields.get(key).ok_or_else(|| invalid(format!("Snapshot is missing '{}'.", key)));
+
+        let mut memory = MemoryKind::Dense.load(vec![]);
+        for cell in field("memory")?.split(',').filter(|s| !s.is_empty()) {
+            match cell.split(':').collect::<Vec<_>>().as_slice() {
+                [address, value] => {
+                    let address = address.parse().map_err(|_| invalid(format!("Invalid address '{}'.", address)))?;
+                    memory.write(address, parse(value)?);
+                },
+                _ => return Err(invalid(format!("Invalid memory cell '{}'.", cell)))
+            }
+        }
+
+        Ok(Snapshot {
+            memory,
+            loc: parse(field("loc")?)?,
+            relative_base: parse(field("relative_base")?)?,
+            running: field("running")?.parse().map_err(|_| invalid(String::from("Invalid running flag.")))?,
+            steps: match fields.get("steps") {
+                Some(steps) => steps.parse().map_err(|_| invalid(format!("Invalid step count '{}'.", steps)))?,
+                None => 0
+            },
+            exit_code: match fields.get("exit_code").map(String::as_str) {
+                Some("") | None => None,
+                Some(code) => Some(parse(code)?)
+            },
+            input: parse_list(field("input")?)?,
+            output: parse_list(field("output")?)?,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(File::create(path)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Snapshot::read_from(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Limits;
+
+    #[test]
+    fn test_restore() {
+        let mut computer = Computer::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0], None, None);
+        computer.push_input(5).unwrap();
+        let snapshot = computer.snapshot();
+        computer.run().unwrap();
+        assert_eq!(vec![6], Vec::from(computer.output()));
+
+        computer.restore(&snapshot);
+        assert_eq!(0, computer.loc());
+        assert_eq!(Ok(0), computer.read(9));
+        assert!(computer.is_running());
+        assert!(computer.output().is_empty());
+        computer.run().unwrap();
+        assert_eq!(vec![6], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_from_snapshot_branches() {
+        let mut computer = Computer::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0], None, None);
+        computer.push_input(1).unwrap();
+        computer.step().unwrap();
+        let snapshot = computer.snapshot();
+
+        let mut branch = Computer::from_snapshot(&snapshot);
+        branch.write(9, 41).unwrap();
+        branch.run().unwrap();
+        computer.run().unwrap();
+        assert_eq!(vec![42], Vec::from(branch.output()));
+        assert_eq!(vec![2], Vec::from(computer.output()));
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let mut computer = Computer::new(vec![109, -3, 3, 9, 104, 7, 99], None, None);
+        computer.push_input(11).unwrap();
+        computer.push_input(12).unwrap();
+        computer.step().unwrap();
+        computer.step().unwrap();
+        computer.step().unwrap();
+        let snapshot = computer.snapshot();
+
+        let mut buffer = vec![];
+        snapshot.write_to(&mut buffer).unwrap();
+        assert_eq!("loc 6\nrelative_base -3\nrunning true\nsteps 3\nexit_code \ninput 12\noutput 7\nmemory 0:109,1:-3,2:3,3:9,4:104,5:7,6:99,7:0,8:0,9:11\n",
+                   String::from_utf8(buffer.clone()).unwrap());
+        assert_eq!(snapshot, Snapshot::read_from(buffer.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(Snapshot::<i32>::read_from("loc 1\n".as_bytes()).is_err());
+        assert!(Snapshot::<i32>::read_from("loc x\nrelative_base 0\nrunning true\ninput\noutput\nmemory\n".as_bytes()).is_err());
+        assert!(Snapshot::<i32>::read_from("loc 0\nrelative_base 0\nrunning true\nsteps -1\ninput\noutput\nmemory\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_without_steps() {
+        let snapshot = Snapshot::<i32>::read_from("loc 0\nrelative_base 0\nrunning true\ninput\noutput\nmemory 0:99\n".as_bytes()).unwrap();
+        let computer = Computer::from_snapshot(&snapshot);
+        assert_eq!(0, computer.steps());
+        assert_eq!(None, computer.exit_code());
+    }
+
+    #[test]
+    fn test_restore_under_step_limit() {
+        let mut computer = Computer::new(vec![1001, 5, 1, 5, 99, 0], None, None);
+        computer.set_limits(Limits { max_steps: Some(5), ..Limits::default() });
+        let snapshot = computer.snapshot();
+        for _ in 0..10 {
+            computer.restore(&snapshot);
+            computer.run().unwrap();
+            assert_eq!(2, computer.steps());
+        }
+        assert_eq!(Ok(1), computer.read(5));
+    }
+}
@@ -0,0 +1,423 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use itertools::Itertools;
+use num::{Integer, Signed};
+
+use super::ArgumentKind;
+
+/// A parameter of a traced instruction, with the memory address it refers to (for position and
+/// relative parameters) and the value it resolved to before the instruction ran.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Operand<T> {
+    pub kind: ArgumentKind,
+    pub raw: T,
+    pub address: Option<T>,
+    pub value: T,
+}
+
+/// A record of a single executed instruction.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TraceEvent<T> {
+    /// The number of instructions executed before this one.
+    pub step: u64,
+    pub address: T,
+    /// The raw instruction value, including parameter modes.
+    pub instruction: T,
+    /// The instruction in assembler syntax.
+    pub text: String,
+    pub operands: Vec<Operand<T>>,
+    /// Memory cells written by the instruction, as (address, new value).
+    pub writes: Vec<(T, T)>,
+    /// The input values the instruction consumed. Only extension instructions take more than one.
+    pub inputs: Vec<T>,
+    /// The values the instruction output. Only extension instructions give more than one.
+    pub outputs: Vec<T>,
+}
+
+/// Receives a `TraceEvent` for every instruction a `Computer` executes.
+pub trait Tracer<T>: Send {
+    fn record(&mut self, event: &TraceEvent<T>);
+
+    /// Flushes any buffered output, reporting the first error encountered while tracing.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sends every event to each tracer in a list.
+impl<T> Tracer<T> for Vec<Box<dyn Tracer<T>>> {
+    fn record(&mut self, event: &TraceEvent<T>) {
+        for tracer in self.iter_mut() {
+            tracer.record(event);
+        }
+    }
+
+    /// Flushes every tracer, reporting the first error.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for tracer in self.iter_mut() {
+            let flushed = tracer.flush();
+            if result.is_ok() {
+                result = flushed;
+            }
+        }
+        result
+    }
+}
+
+/// Combines several tracers into one, returning `None` if there are none.
+pub fn combine<T: 'static>(mut tracers: Vec<Box<dyn Tracer<T>>>) -> Option<Box<dyn Tracer<T>>> {
+    match tracers.len() {
+        0 => None,
+        1 => tracers.pop(),
+        _ => Some(Box::new(tracers))
+    }
+}
+
+/// A tracer that keeps every event in memory. Clones share the same buffer, so a clone can be
+/// given to a computer while the original is used to read the events back.
+#[derive(Clone, Default)]
+pub struct BufferTracer<T> {
+    events: Arc<Mutex<Vec<TraceEvent<T>>>>,
+}
+
+impl<T: Clone> BufferTracer<T> {
+    pub fn new() -> Self {
+        BufferTracer { events: Arc::new(Mutex::new(vec![])) }
+    }
+
+    pub fn events(&self) -> Vec<TraceEvent<T>> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl<T: Clone + Send> Tracer<T> for BufferTracer<T> {
+    fn record(&mut self, event: &TraceEvent<T>) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// A tracer that writes one human-readable line per instruction, such as
+/// `     3     12: add [4], #3, [9]   [4]=7 #3 [9]=0  [9]<-10`.
+pub struct LogTracer<W> {
+    writer: W,
+    label: Option<String>,
+    error: Option<io::Error>,
+}
+
+/// A tracer that writes one tab-separated line per instruction: step, address, raw instruction,
+/// operands as `mode:raw:value`, writes as `address=value`, input and output, with `-` marking
+/// an empty field.
+pub struct CompactTracer<W> {
+    writer: W,
+    label: Option<String>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> LogTracer<W> {
+    pub fn new(writer: W) -> Self {
+        LogTracer { writer, label: None, error: None }
+    }
+
+    /// Prefixes every line with a label, to distinguish computers sharing a writer.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(String::from(label));
+        self
+    }
+}
+
+impl<W: Write> CompactTracer<W> {
+    pub fn new(writer: W) -> Self {
+        CompactTracer { writer, label: None, error: None }
+    }
+
+    /// Adds a label as the first field of every line, to distinguish computers sharing a writer.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(String::from(label));
+        self
+    }
+}
+
+impl<T, W> Tracer<T> for LogTracer<W> where T: Integer + Signed + fmt::Display, W: Write + Send {
+    fn record(&mut self, event: &TraceEvent<T>) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut line = match &self.label {
+            Some(label) => format!("{} ", label),
+            None => String::new()
+        };
+        line += &format!("{:>6} {:>6}: {:<28} {}", event.step, event.address, event.text, event.operands.iter().map(describe_operand).join(" "));
+        if !event.writes.is_empty() {
+            line += &format!("  {}", event.writes.iter().map(|(a, v)| format!("[{}]<-{}", a, v)).join(" "));
+        }
+        if !event.inputs.is_empty() {
+            line += &format!("  input {}", event.inputs.iter().join(","));
+        }
+        if !event.outputs.is_empty() {
+            line += &format!("  output {}", event.outputs.iter().join(","));
+        }
+        if let Err(e) = writeln!(self.writer, "{}", line.trim_end()) {
+            self.error = Some(e);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush()
+        }
+    }
+}
+
+impl<T, W> Tracer<T> for CompactTracer<W> where T: Integer + Signed + fmt::Display, W: Write + Send {
+    fn record(&mut self, event: &TraceEvent<T>) {
+        if self.error.is_some() {
+            return;
+        }
+        let field = |text: String| if text.is_empty() { String::from("-") } else { text };
+        let mut fields = vec![];
+        if let Some(label) = &self.label {
+            fields.push(label.clone());
+        }
+        fields.push(event.step.to_string());
+        fields.push(event.address.to_string());
+        fields.push(event.instruction.to_string());
+        fields.push(field(event.operands.iter().map(|o| format!("{}:{}:{}", mode(&o.kind), o.raw, o.value)).join(",")));
+        fields.push(field(event.writes.iter().map(|(a, v)| format!("{}={}", a, v)).join(",")));
+        fields.push(field(event.inputs.iter().join(",")));
+        fields.push(field(event.outputs.iter().join(",")));
+        if let Err(e) = writeln!(self.writer, "{}", fields.join("\t")) {
+            self.error = Some(e);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush()
+        }
+    }
+}
+
+fn mode(kind: &ArgumentKind) -> u8 {
+    match kind {
+        ArgumentKind::Position => 0,
+        ArgumentKind::Immediate => 1,
+        ArgumentKind::Relative => 2
+    }
+}
+
+fn describe_operand<T: Integer + Signed + fmt::Display>(operand: &Operand<T>) -> String {
+    match (&operand.kind, &operand.address) {
+        (ArgumentKind::Immediate, _) => format!("#{}", operand.raw),
+        (ArgumentKind::Relative, Some(address)) => format!("[{}]={}", address, operand.value),
+        (_, _) => format!("[{}]={}", operand.raw, operand.value)
+    }
+}
+
+/// A writer that can be shared between several tracers, for example one per amplifier.
+///
+/// The first write to fail is remembered and reported by the next `flush` through any clone, so
+/// that errors met by tracers that are never flushed themselves aren't lost.
+#[derive(Clone)]
+pub struct SharedWriter {
+    inner: Arc<Mutex<Box<dyn Write + Send>>>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl SharedWriter {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        SharedWriter { inner: Arc::new(Mutex::new(Box::new(writer))), error: Arc::new(Mutex::new(None)) }
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock().unwrap().write(buf).inspect_err(|e| {
+            self.error.lock().unwrap().get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => self.inner.lock().unwrap().flush()
+        }
+    }
+}
+
+/// The file formats a trace can be written in.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum TraceFormat {
+    Log,
+    Compact,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(TraceFormat::Log),
+            "compact" => Ok(TraceFormat::Compact),
+            _ => Err(format!("Unknown trace format '{}': expected log or compact.", s))
+        }
+    }
+}
+
+/// Where and how to write a trace file.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TraceSettings {
+    pub path: PathBuf,
+    pub format: TraceFormat,
+}
+
+impl TraceSettings {
+    /// Creates the trace file.
+    pub fn open(&self) -> io::Result<TraceFile> {
+        let writer = SharedWriter::new(BufWriter::new(File::create(&self.path)?));
+        Ok(TraceFile { format: self.format, writer })
+    }
+}
+
+/// An open trace file, which any number of computers can write to.
+pub struct TraceFile {
+    format: TraceFormat,
+    writer: SharedWriter,
+}
+
+impl TraceFile {
+    /// Makes a tracer writing to the file in its format, labelled if a label is given.
+    pub fn tracer<T>(&self, label: Option<&str>) -> Box<dyn Tracer<T>>
+    where
+        T: Integer + Signed + fmt::Display + 'static,
+    {
+        match (self.format, label) {
+            (TraceFormat::Log, Some(l)) => Box::new(LogTracer::new(self.writer.clone()).with_label(l)),
+            (TraceFormat::Log, None) => Box::new(LogTracer::new(self.writer.clone())),
+            (TraceFormat::Compact, Some(l)) => Box::new(CompactTracer::new(self.writer.clone()).with_label(l)),
+            (TraceFormat::Compact, None) => Box::new(CompactTracer::new(self.writer.clone())),
+        }
+    }
+
+    /// Flushes the file, reporting the first error any of its tracers met while writing.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::intcode::{Computer, ComputerInput};
+
+    fn traced(program: Vec<i32>, input: Vec<i32>) -> Vec<TraceEvent<i32>> {
+        let buffer = BufferTracer::new();
+        let mut computer = Computer::new(program, Some(ComputerInput::Queue(VecDeque::from(input))), None);
+        computer.set_tracer(Box::new(buffer.clone()));
+        computer.run().unwrap();
+        buffer.events()
+    }
+
+    #[test]
+    fn test_buffer_records_operands_and_writes() {
+        let events = traced(vec![1001, 5, 3, 6, 99, 4, 0], vec![]);
+        assert_eq!(2, events.len());
+        assert_eq!(TraceEvent {
+            step: 0,
+            address: 0,
+            instruction: 1001,
+            text: String::from("add [5], #3, [6]"),
+            operands: vec![
+                Operand { kind: ArgumentKind::Position, raw: 5, address: Some(5), value: 4 },
+                Operand { kind: ArgumentKind::Immediate, raw: 3, address: None, value: 3 },
+                Operand { kind: ArgumentKind::Position, raw: 6, address: Some(6), value: 0 },
+            ],
+            writes: vec![(6, 7)],
+            inputs: vec![],
+            outputs: vec![],
+        }, events[0]);
+        assert_eq!("hlt", events[1].text);
+        assert_eq!(1, events[1].step);
+    }
+
+    #[test]
+    fn test_buffer_records_io() {
+        let events = traced(vec![109, 10, 203, -3, 204, -3, 99], vec![42]);
+        assert_eq!(vec![42], events[1].inputs);
+        assert_eq!(vec![(7, 42)], events[1].writes);
+        assert_eq!(vec![42], events[2].outputs);
+        assert_eq!(Some(7), events[2].operands[0].address);
+    }
+
+    #[test]
+    fn test_log_format() {
+        let mut tracer = LogTracer::new(vec![]).with_label("A");
+        for event in traced(vec![3, 5, 4, 5, 99, 0], vec![8]) {
+            tracer.record(&event);
+        }
+        let text = String::from_utf8(tracer.writer).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(format!("A {:>6} {:>6}: {:<28} [5]=0  [5]<-8  input 8", 0, 0, "in [5]"), lines[0]);
+        assert_eq!(format!("A {:>6} {:>6}: {:<28} [5]=8  output 8", 1, 2, "out [5]"), lines[1]);
+        assert_eq!(format!("A {:>6} {:>6}: hlt", 2, 4), lines[2]);
+    }
+
+    #[test]
+    fn test_compact_format() {
+        let mut tracer = CompactTracer::new(vec![]);
+        for event in traced(vec![3, 5, 104, 9, 99, 0], vec![8]) {
+            tracer.record(&event);
+        }
+        assert_eq!("0\t0\t3\t0:5:0\t5=8\t8\t-\n1\t2\t104\t1:9:9\t-\t-\t9\n2\t4\t99\t-\t-\t-\t-\n",
+                   String::from_utf8(tracer.writer).unwrap());
+    }
+
+    #[test]
+    fn test_combine() {
+        let (first, second) = (BufferTracer::new(), BufferTracer::new());
+        assert!(combine::<i32>(vec![]).is_none());
+        let mut tracer = combine(vec![Box::new(first.clone()), Box::new(second.clone())]).unwrap();
+        for event in traced(vec![104, 1, 99], vec![]) {
+            tracer.record(&event);
+        }
+        assert_eq!(2, first.events().len());
+        assert_eq!(first.events(), second.events());
+    }
+
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_file_reports_tracer_errors() {
+        let mut file = TraceFile { format: TraceFormat::Log, writer: SharedWriter::new(FullDisk) };
+        let mut tracer = file.tracer::<i32>(Some("a"));
+        for event in traced(vec![104, 1, 99], vec![]) {
+            tracer.record(&event);
+        }
+        drop(tracer);
+        assert_eq!("disk full", file.flush().unwrap_err().to_string());
+        assert!(file.flush().is_ok());
+    }
+
+    #[test]
+    fn test_trace_format_from_str() {
+        assert_eq!(Ok(TraceFormat::Compact), "compact".parse());
+        assert!("xml".parse::<TraceFormat>().is_err());
+    }
+}
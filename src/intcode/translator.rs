@@ -0,0 +1,412 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+use itertools::Itertools;
+use num::{Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use super::{Argument, ArgumentKind, Instruction};
+use super::analysis::{Block, ControlFlowGraph, Exit};
+
+/// Support code shared by every translated program: memory, an interpreter for anything that
+/// couldn't be translated, and tracking of which translated blocks are still valid.
+const RUNTIME: &str = r#"    const DENSE_LIMIT: usize = 1 << 20;
+
+    struct Machine {
+        mem: Vec<i64>,
+        far: HashMap<usize, i64>,
+        rb: i64,
+        code: Vec<bool>,
+        valid: Vec<bool>,
+    }
+
+    impl Machine {
+        fn peek(&self, address: usize) -> i64 {
+            match self.mem.get(address) {
+                Some(&value) => value,
+                None => self.far.get(&address).copied().unwrap_or(0),
+            }
+        }
+
+        fn locate(&self, address: i64, at: usize) -> Result<usize, String> {
+            if address < 0 {
+                Err(format!("Instruction {} at address {} accesses negative address {}.", self.peek(at), at, address))
+            } else {
+                Ok(address as usize)
+            }
+        }
+
+        /// The address a relative parameter refers to.
+        fn relative(&self, value: i64, at: usize) -> Result<i64, String> {
+            self.rb.checked_add(value)
+                .ok_or_else(|| format!("Arithmetic overflow in instruction {} at address {}.", self.peek(at), at))
+        }
+
+        fn read(&self, address: i64, at: usize) -> Result<i64, String> {
+            Ok(self.peek(self.locate(address, at)?))
+        }
+
+        /// Writes a value, returning whether it landed on translated code.
+        fn write(&mut self, address: i64, value: i64, at: usize) -> Result<bool, String> {
+            let address = self.locate(address, at)?;
+            if address < self.mem.len() {
+                self.mem[address] = value;
+            } else if address < DENSE_LIMIT {
+                self.mem.resize(address + 1, 0);
+                self.mem[address] = value;
+            } else {
+                self.far.insert(address, value);
+            }
+            if !self.code.get(address).copied().unwrap_or(false) {
+                return Ok(false);
+            }
+            for (valid, &(start, end)) in self.valid.iter_mut().zip(BLOCKS.iter()) {
+                if start <= address && address < end {
+                    *valid = false;
+                }
+            }
+            Ok(true)
+        }
+
+        fn get(&self, mode: i64, value: i64, at: usize) -> Result<i64, String> {
+            match mode {
+                1 => Ok(value),
+                2 => self.read(self.relative(value, at)?, at),
+                _ => self.read(value, at),
+            }
+        }
+
+        fn put(&mut self, mode: i64, value: i64, result: i64, at: usize) -> Result<bool, String> {
+            match mode {
+                1 => Err(format!("Instruction {} at address {} writes to an immediate parameter.", self.peek(at), at)),
+                2 => self.write(self.relative(value, at)?, result, at),
+                _ => self.write(value, result, at),
+            }
+        }
+
+        /// Interprets the instruction at `pc`, returning the address of the next one, or `None`
+        /// if it halts.
+        fn step(&mut self, pc: i64, input: &mut VecDeque<i64>, output: &mut VecDeque<i64>) -> Result<Option<i64>, String> {
+            if pc < 0 {
+                return Err(format!("Instruction 0 at address {} accesses negative address {}.", pc, pc));
+            }
+            let at = pc as usize;
+            let code = self.peek(at);
+            if code < 1 {
+                return Err(format!("Unknown opcode in instruction {} at address {}.", code, at));
+            }
+            let mut modes = [0; 3];
+            let mut rest = code / 100;
+            let mut n = 0;
+            while rest != 0 {
+                if rest % 10 > 2 {
+                    return Err(format!("Bad parameter mode in instruction {} at address {}.", code, at));
+                }
+                if n < modes.len() {
+                    modes[n] = rest % 10;
+                }
+                rest /= 10;
+                n += 1;
+            }
+            let p = [self.peek(at + 1), self.peek(at + 2), self.peek(at + 3)];
+            let next = match code % 100 {
+                1 | 2 | 7 | 8 => {
+                    let a = self.get(modes[0], p[0], at)?;
+                    let b = self.get(modes[1], p[1], at)?;
+                    let result = match code % 100 {
+                        1 => a + b,
+                        2 => a * b,
+                        7 => (a < b) as i64,
+                        _ => (a == b) as i64,
+                    };
+                    self.put(modes[2], p[2], result, at)?;
+                    pc + 4
+                },
+                3 => {
+                    let value = input.pop_front()
+                        .ok_or_else(|| format!("Failed to find an input value for instruction {} at address {}.", code, at))?;
+                    self.put(modes[0], p[0], value, at)?;
+                    pc + 2
+                },
+                4 => {
+                    output.push_back(self.get(modes[0], p[0], at)?);
+                    pc + 2
+                },
+                5 | 6 => {
+                    if (self.get(modes[0], p[0], at)? != 0) == (code % 100 == 5) {
+                        self.get(modes[1], p[1], at)?
+                    } else {
+                        pc + 3
+                    }
+                },
+                9 => {
+                    self.rb += self.get(modes[0], p[0], at)?;
+                    pc + 2
+                },
+                99 => return Ok(None),
+                _ => return Err(format!("Unknown opcode in instruction {} at address {}.", code, at)),
+            };
+            Ok(Some(next))
+        }
+    }
+
+    let mut m = Machine { mem: PROGRAM.to_vec(), far: HashMap::new(), rb: 0, code: vec![false; PROGRAM.len()], valid: vec![true; BLOCKS.len()] };
+    for &(start, end) in BLOCKS.iter() {
+        for word in &mut m.code[start..end] {
+            *word = true;
+        }
+    }
+"#;
+
+/// Translates a program image into the source of a standalone Rust function called `name`.
+///
+/// The function takes the place of `Computer::new` followed by `Computer::run`: it reads input
+/// from a queue, appends output to another, and returns the value at address 0 once the program
+/// halts. Errors are reported with the same messages as `IntcodeError`. Values are `i64`, and
+/// arithmetic behaves as it does for an unchecked `Computer<i64>`.
+///
+/// The basic blocks found by `ControlFlowGraph` are translated into straight-line Rust. Anything
+/// else - the targets of computed jumps, and blocks whose code has been overwritten while
+/// running - is run by an interpreter embedded in the function, which hands back to translated
+/// code whenever it reaches the start of a block that is still intact.
+pub fn translate<T>(program: &[T], name: &str) -> Result<String, String>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !identifier {
+        return Err(format!("'{}' is not a valid function name.", name));
+    }
+    let image: Vec<i64> = program.iter().enumerate()
+        .map(|(address, value)| value.to_i64().ok_or_else(|| format!("Value {} at address {} doesn't fit in an i64.", value, address)))
+        .collect::<Result<_, _>>()?;
+
+    let graph = ControlFlowGraph::new(program);
+    let blocks: Vec<&Block<T>> = graph.blocks.values().filter(|block| !block.instructions.is_empty()).collect();
+    let code: BTreeSet<usize> = blocks.iter().flat_map(|block| block.start..end(block)).collect();
+
+    let mut source = String::new();
+    writeln!(source, "/// Runs an Intcode program translated to Rust, returning the value at address 0 when it halts.").unwrap();
+    writeln!(source, "pub fn {}(input: &mut std::collections::VecDeque<i64>, output: &mut std::collections::VecDeque<i64>) -> Result<i64, String> {{", name).unwrap();
+    writeln!(source, "    use std::collections::{{HashMap, VecDeque}};\n").unwrap();
+    writeln!(source, "    const PROGRAM: [i64; {}] = [", image.len()).unwrap();
+    for row in image.chunks(16) {
+        writeln!(source, "        {},", row.iter().join(", ")).unwrap();
+    }
+    writeln!(source, "    ];").unwrap();
+    writeln!(source, "    const BLOCKS: [(usize, usize); {}] = [{}];\n", blocks.len(),
+             blocks.iter().map(|block| format!("({}, {})", block.start, end(block))).join(", ")).unwrap();
+    source.push_str(RUNTIME);
+
+    writeln!(source, "\n    let mut pc: i64 = 0;\n    loop {{\n        match pc {{").unwrap();
+    for (index, block) in blocks.iter().enumerate() {
+        writeln!(source, "            {} if m.valid[{}] => {{", block.start, index).unwrap();
+        for line in translate_block(block, &image, &code) {
+            writeln!(source, "                {}", line).unwrap();
+        }
+        writeln!(source, "            }},").unwrap();
+    }
+    writeln!(source, "            _ => {{}}\n        }}").unwrap();
+    source.push_str("        match m.step(pc, input, output)? {\n            Some(next) => pc = next,\n            None => return Ok(m.peek(0)),\n        }\n    }\n}\n");
+
+    Ok(source)
+}
+
+/// The address just past the last word of a block.
+fn end<T>(block: &Block<T>) -> usize
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    block.instructions.last().map_or(block.start, |(address, instruction)| address + instruction.arguments().len() + 1)
+}
+
+/// Translates the instructions of a block into statements, ending by setting `pc` to the next
+/// block and continuing, or by returning.
+fn translate_block<T>(block: &Block<T>, image: &[i64], code: &BTreeSet<usize>) -> Vec<String>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + fmt::Display + Send + 'static,
+{
+    let mut lines = vec![];
+    for (address, instruction) in &block.instructions {
+        let at = *address;
+        let next = at + instruction.arguments().len() + 1;
+        lines.push(format!("// {}: {}", at, instruction));
+        match instruction {
+            Instruction::Add(a, b, c) | Instruction::Multiply(a, b, c) | Instruction::LessThan(a, b, c) | Instruction::Equals(a, b, c) => {
+                lines.push(format!("let a = {};", read(a, at)));
+                lines.push(format!("let b = {};", read(b, at)));
+                let result = match instruction {
+                    Instruction::Add(..) => "a + b",
+                    Instruction::Multiply(..) => "a * b",
+                    Instruction::LessThan(..) => "(a < b) as i64",
+                    _ => "(a == b) as i64",
+                };
+                lines.push(write(c, result, at, next, code));
+            },
+            Instruction::Input(a) => {
+                lines.push(format!("let v = input.pop_front().ok_or(\"Failed to find an input value for instruction {} at address {}.\")?;", image[at], at));
+                lines.push(write(a, "v", at, next, code));
+            },
+            Instruction::Output(a) => lines.push(format!("output.push_back({});", read(a, at))),
+            Instruction::AdjustRelativeBase(a) => lines.push(format!("m.rb += {};", read(a, at))),
+            Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) | Instruction::Stop => {},
+            Instruction::Extension { .. } => unreachable!("Extension opcodes are never found by static analysis.")
+        }
+    }
+
+    let jump = match block.instructions.last() {
+        Some((at, Instruction::JumpIfTrue(a, b))) => Some((format!("{} != 0", read(a, *at)), read(b, *at))),
+        Some((at, Instruction::JumpIfFalse(a, b))) => Some((format!("{} == 0", read(a, *at)), read(b, *at))),
+        _ => None
+    };
+    match (&block.exit, jump) {
+        (Exit::Halt, _) => lines.push(String::from("return Ok(m.peek(0));")),
+        (Exit::Branch { taken, not_taken }, Some((condition, _))) =>
+            lines.push(format!("pc = if {} {{ {} }} else {{ {} }};", condition, taken, not_taken)),
+        (Exit::Computed { not_taken: Some(not_taken) }, Some((condition, target))) =>
+            lines.push(format!("pc = if {} {{ {} }} else {{ {} }};", condition, target, not_taken)),
+        (Exit::Computed { not_taken: None }, Some((_, target))) => lines.push(format!("pc = {};", target)),
+        (Exit::Goto(next), _) | (Exit::Invalid(next), _) => lines.push(format!("pc = {};", next)),
+        (_, None) => unreachable!("Only jumps end a block with a branch."),
+    }
+    if block.exit != Exit::Halt {
+        lines.push(String::from("continue;"));
+    }
+    lines
+}
+
+/// An expression for the value of an argument.
+fn read<T: Integer + Signed + Clone + CheckedAdd + ToPrimitive + fmt::Display>(argument: &Argument<T>, at: usize) -> String {
+    match argument.kind() {
+        ArgumentKind::Immediate => argument.value().to_string(),
+        _ => format!("m.read({}, {})?", address(argument, at), at),
+    }
+}
+
+/// An expression for the address an argument refers to, using the relative base at run time.
+fn address<T: Integer + Signed + Clone + CheckedAdd + ToPrimitive + fmt::Display>(argument: &Argument<T>, at: usize) -> String {
+    match argument.kind() {
+        ArgumentKind::Relative => format!("m.relative({}, {})?", argument.value(), at),
+        _ => argument.value().to_string(),
+    }
+}
+
+/// A statement writing a result. Writes that might land on translated code stop the block
+/// afterwards, so that the rest of it is checked again before running.
+fn write<T>(argument: &Argument<T>, result: &str, at: usize, next: usize, code: &BTreeSet<usize>) -> String
+where
+    T: Integer + Signed + Clone + CheckedAdd + ToPrimitive + fmt::Display,
+{
+    let statement = format!("m.write({}, {}, {})?", address(argument, at), result, at);
+    let hits_code = match argument.kind() {
+        ArgumentKind::Position => argument.value().to_usize().is_some_and(|target| code.contains(&target)),
+        _ => true,
+    };
+    if hits_code {
+        format!("if {} {{ pc = {}; continue; }}", statement, next)
+    } else {
+        format!("{};", statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::process::Command;
+    use crate::util;
+    use crate::intcode::{Computer, ComputerInput};
+    use crate::intcode::assembler::assemble;
+
+    fn load(filename: &str) -> Vec<i64> {
+        util::read_comma_separated_integers(File::open(filename).unwrap()).unwrap()
+    }
+
+    /// Runs a program in the interpreter, describing the outcome the same way as the harness
+    /// built around translated programs.
+    fn interpret(program: &[i64], input: &[i64]) -> String {
+        let mut computer = Computer::new(program.to_vec(), Some(ComputerInput::Queue(input.iter().cloned().collect())), None);
+        let result = computer.run().map_err(|e| e.to_string());
+        format!("{:?} {:?}", Vec::from(computer.output()), result)
+    }
+
+    #[test]
+    fn test_translation_matches_interpreter() {
+        let mut day2 = load("inputs/day2.txt");
+        day2[1] = 12;
+        day2[2] = 2;
+        let countdown = assemble::<i64>("
+            loop:   arb #1
+                    out rb+11
+                    add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      .data 3
+                    .data 7
+                    .data 8
+                    .data 9").unwrap();
+        let computed = assemble::<i64>("
+                    in [t]
+                    jt #1, [t]
+                    out #1
+                    hlt
+            t:      .data 0").unwrap();
+        let self_modifying = vec![104, 1, 1001, 1, 1, 1, 1007, 1, 4, 14, 1005, 14, 0, 99, 0];
+        let near_the_limit = vec![109, i64::MAX, 204, 1, 99];
+
+        let mut programs = vec![day2, load("inputs/day5.txt"), load("inputs/day7.txt"), load("inputs/day9.txt"), countdown, computed, self_modifying, near_the_limit];
+        let mut cases: Vec<(usize, Vec<i64>)> = vec![
+            (0, vec![]), (1, vec![1]), (1, vec![5]), (1, vec![]), (2, vec![0, 0]), (2, vec![3, 17]),
+            (3, vec![1]), (3, vec![2]), (4, vec![]), (5, vec![5]), (5, vec![9]), (5, vec![-1]), (6, vec![]), (7, vec![]),
+        ];
+        for test in 1..=7 {
+            programs.push(load(&format!("inputs/day5_p2_t{}.txt", test)));
+            for input in &[0, 7, 8, 9] {
+                cases.push((programs.len() - 1, vec![*input]));
+            }
+        }
+
+        let mut source = String::from("use std::collections::VecDeque;\n\n");
+        for (index, program) in programs.iter().enumerate() {
+            source.push_str(&translate(program, &format!("program{}", index)).unwrap());
+        }
+        source.push_str("\nfn run(program: fn(&mut VecDeque<i64>, &mut VecDeque<i64>) -> Result<i64, String>, input: &[i64]) {\n");
+        source.push_str("    let mut input: VecDeque<i64> = input.iter().cloned().collect();\n    let mut output = VecDeque::new();\n");
+        source.push_str("    let result = program(&mut input, &mut output);\n    println!(\"{:?} {:?}\", Vec::from(output), result);\n}\n\nfn main() {\n");
+        for (index, input) in &cases {
+            source.push_str(&format!("    run(program{}, &{:?});\n", index, input));
+        }
+        source.push_str("}\n");
+
+        let directory = env::temp_dir().join(format!("intcode-translator-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (main, binary) = (directory.join("main.rs"), directory.join("main"));
+        fs::write(&main, source).unwrap();
+        let compiled = Command::new(env::var("RUSTC").unwrap_or_else(|_| String::from("rustc")))
+            .arg("--edition=2018").arg("-o").arg(&binary).arg(&main)
+            .output().unwrap();
+        assert!(compiled.status.success(), "{}", String::from_utf8_lossy(&compiled.stderr));
+        let run = Command::new(&binary).output().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let expected: Vec<String> = cases.iter().map(|(index, input)| interpret(&programs[*index], input)).collect();
+        assert_eq!(expected, String::from_utf8(run.stdout).unwrap().lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_translate_blocks() {
+        let source = translate(&[1005, 5, 4, 99, 99, 0], "branch").unwrap();
+        assert!(source.starts_with("/// Runs an Intcode program"));
+        assert!(source.contains("pub fn branch(input: &mut std::collections::VecDeque<i64>"));
+        assert!(source.contains("const BLOCKS: [(usize, usize); 3] = [(0, 3), (3, 4), (4, 5)];"));
+        assert!(source.contains("pc = if m.read(5, 0)? != 0 { 4 } else { 3 };"));
+    }
+
+    #[test]
+    fn test_translate_rejects_bad_input() {
+        assert!(translate(&[99], "not a name").is_err());
+        assert!(translate(&[99], "1st").is_err());
+        assert!(translate(&[99i128, i128::MAX], "wide").is_err());
+    }
+}
@@ -13,21 +13,27 @@ pub mod day9;
 pub mod day10;
 pub mod util;
 pub mod intcode;
+pub mod tools;
 use options::Opt;
 use options::Command;
 
 pub fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    let trace = opt.trace_settings();
     match opt.cmd {
         Command::Day1 { filename } => day1::run_day1(&filename, &opt.part2),
-        Command::Day2 { filename } => day2::run(&filename, &opt.part2),
+        Command::Day2 { filename } => day2::run(&filename, &opt.part2, &trace),
         Command::Day3 { filename } => day3::run(&filename, &opt.part2),
         Command::Day4 { range_start, range_end } => day4::run(range_start, range_end, &opt.part2),
-        Command::Day5 { filename } => day5::run(&filename, &opt.part2),
+        Command::Day5 { filename } => day5::run(&filename, &opt.part2, &trace),
         Command::Day6 { filename } => day6::run(&filename, &opt.part2),
-        Command::Day7 { filename } => day7::run(&filename, &opt.part2),
+        Command::Day7 { filename, amplifiers, phases, workers, table } => {
+            let settings = day7::Settings { amplifiers, phases, workers, table };
+            day7::run(&filename, &opt.part2, &trace, &opt.record_io, &settings)
+        },
         Command::Day8 { filename } => day8::run(&filename, &opt.part2),
-        Command::Day9 { filename } => day9::run(&filename, &opt.part2),
+        Command::Day9 { filename } => day9::run(&filename, &opt.part2, &trace),
         Command::Day10 { filename } => day10::run(&filename, &opt.part2),
+        Command::Intcode { cmd } => tools::run(&cmd),
     }
 }
 
@@ -1,7 +1,6 @@
 use std::process;
 use structopt::StructOpt;
 
-mod options;
 pub use adventlib::options::Opt;
 
 extern crate num;
@@ -1,6 +1,10 @@
+use std::ops::Range;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
+use crate::intcode::trace::{TraceFormat, TraceSettings};
+
 #[derive(Debug, StructOpt)]
 #[structopt(about="Application for solving Advent of Code 2019 puzzles.")]
 pub struct Opt {
@@ -8,10 +12,28 @@ pub struct Opt {
     /// Specify that Part 2 of the solution is to be run.
     pub part2: bool,
 
+    #[structopt(long)]
+    /// Write a trace of every Intcode instruction executed to this file (Days 2, 5, 7 and 9).
+    pub trace: Option<PathBuf>,
+
+    #[structopt(long, default_value = "log")]
+    /// The format of the trace file: log or compact.
+    pub trace_format: TraceFormat,
+
+    #[structopt(long)]
+    /// Record every value passed into and out of the Intcode computers to this file (Day 7).
+    pub record_io: Option<PathBuf>,
+
     #[structopt(subcommand)]
     pub cmd: Command
 }
 
+impl Opt {
+    pub fn trace_settings(&self) -> Option<TraceSettings> {
+        self.trace.as_ref().map(|path| TraceSettings { path: path.clone(), format: self.trace_format })
+    }
+}
+
 #[derive(Debug, StructOpt)]
 /// Run the solution for Day 1.
 pub enum Command {
@@ -57,6 +79,18 @@ pub enum Command {
     Day7 {
         /// The name of the file to be used for inuput.
         filename: PathBuf,
+        /// The number of amplifiers.
+        #[structopt(short, long, default_value = "5", parse(try_from_str = parse_amplifiers))]
+        amplifiers: usize,
+        /// The phase settings to try, as start..end or start..=last. Defaults to 0..5, or 5..10 for Part 2.
+        #[structopt(short, long, parse(try_from_str = parse_range))]
+        phases: Option<Range<i32>>,
+        /// The number of threads to search with. Defaults to the number of CPUs, or 1 when tracing or recording I/O.
+        #[structopt(short, long)]
+        workers: Option<usize>,
+        /// Print the signal produced by every ordering of the phase settings.
+        #[structopt(long)]
+        table: bool,
     },
 
     /// Image processing
@@ -76,4 +110,179 @@ pub enum Command {
         /// The name of the file to be used for input.
         filename: PathBuf,        
     },
+
+    /// Intcode tools
+    Intcode {
+        #[structopt(subcommand)]
+        cmd: IntcodeCommand,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+/// Tools for working with Intcode programs.
+pub enum IntcodeCommand {
+    /// Assemble an Intcode assembly file into a comma-separated program.
+    Assemble {
+        /// The name of the assembly file.
+        filename: PathBuf,
+        /// The file to write the program to, instead of standard output.
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Disassemble a comma-separated Intcode program into an annotated listing.
+    Disassemble {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+    },
+
+    /// Write the control-flow graph of an Intcode program in Graphviz DOT format.
+    Graph {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+        /// The file to write the graph to, instead of standard output.
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Translate an Intcode program into the source of a Rust function.
+    Translate {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+        /// The name to give the function.
+        #[structopt(short, long, default_value = "run")]
+        name: String,
+        /// The file to write the source to, instead of standard output.
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Run an Intcode program, printing its output and the final value at address 0.
+    ///
+    /// Input is read from standard input as it's needed, unless --input or --input-file is given.
+    Run {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+        /// Treat input and output as ASCII text, printing any other output values at the end.
+        #[structopt(long)]
+        ascii: bool,
+        /// Comma-separated values to give the program as input.
+        #[structopt(short, long, require_delimiter = true, allow_hyphen_values = true, conflicts_with_all = &["ascii", "input-file"])]
+        input: Vec<i128>,
+        /// A file holding the program's input: values separated by commas or whitespace, or text with --ascii.
+        #[structopt(long)]
+        input_file: Option<PathBuf>,
+        /// Set memory before running, as address=value. May be given more than once.
+        #[structopt(short, long, parse(try_from_str = parse_assignment), number_of_values = 1)]
+        set: Vec<(i128, i128)>,
+        /// The size of the computer's integers, in bits: 32, 64 or 128, or big for arbitrary precision.
+        #[structopt(short, long, default_value = "64")]
+        width: Width,
+        /// Stop with an error if an instruction's result doesn't fit in the chosen width.
+        #[structopt(long)]
+        checked: bool,
+    },
+
+    /// Run an Intcode program under an interactive debugger.
+    Debug {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+    },
+
+    /// Run an Intcode program and report where it spends its time.
+    Profile {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+        /// Comma-separated values to give the program as input.
+        #[structopt(short, long, require_delimiter = true, allow_hyphen_values = true)]
+        input: Vec<i64>,
+        /// The number of hotspot addresses to list.
+        #[structopt(short, long, default_value = "20")]
+        top: usize,
+        /// Annotate each hotspot with its disassembly.
+        #[structopt(short, long)]
+        disassemble: bool,
+        /// Stop the program after this many instructions.
+        #[structopt(long)]
+        max_steps: Option<u64>,
+    },
+
+    /// Run randomly generated programs, checking that the computer never panics and that its
+    /// memory backends and execution modes agree.
+    Fuzz {
+        /// The number of programs to generate.
+        #[structopt(short, long, default_value = "10000")]
+        iterations: usize,
+        /// The seed for the random number generator.
+        #[structopt(short, long, default_value = "2019")]
+        seed: u64,
+        /// Stop each program after this many instructions.
+        #[structopt(long, default_value = "1000")]
+        max_steps: u64,
+    },
+
+    /// Replay machines from an I/O log, checking that each reproduces its recorded outputs.
+    Replay {
+        /// The name of the file containing the program.
+        filename: PathBuf,
+        /// The I/O log written by --record-io.
+        log: PathBuf,
+        /// Replay only this machine, instead of every machine in the log.
+        #[structopt(short, long)]
+        machine: Option<String>,
+    },
+}
+
+/// The integer sizes an Intcode computer can be run with.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Width {
+    W32,
+    W64,
+    W128,
+    Big,
+}
+
+impl FromStr for Width {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "32" => Ok(Width::W32),
+            "64" => Ok(Width::W64),
+            "128" => Ok(Width::W128),
+            "big" => Ok(Width::Big),
+            _ => Err(format!("Unknown width '{}': expected 32, 64, 128 or big.", s))
+        }
+    }
+}
+
+/// Parses a memory assignment written as `address=value`.
+fn parse_assignment(s: &str) -> Result<(i128, i128), String> {
+    let invalid = || format!("Invalid assignment '{}', expected address=value.", s);
+    let (address, value) = s.split_at(s.find('=').ok_or_else(invalid)?);
+    Ok((address.trim().parse().map_err(|_| invalid())?, value[1..].trim().parse().map_err(|_| invalid())?))
+}
+
+/// Parses a range written as `start..end` or `start..=last`.
+fn parse_range(s: &str) -> Result<Range<i32>, String> {
+    let invalid = || format!("Invalid range '{}', expected start..end or start..=last.", s);
+    let (start, end, inclusive) = match s.find("..=") {
+        Some(i) => (&s[..i], &s[i + 3..], true),
+        None => {
+            let i = s.find("..").ok_or_else(invalid)?;
+            (&s[..i], &s[i + 2..], false)
+        }
+    };
+    let start: i32 = start.trim().parse().map_err(|_| invalid())?;
+    let end: i32 = end.trim().parse().map_err(|_| invalid())?;
+    Ok(if inclusive { start..end.checked_add(1).ok_or_else(invalid)? } else { start..end })
+}
+
+/// Parses a number of amplifiers, which must be at least one.
+fn parse_amplifiers(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err(String::from("There must be at least one amplifier.")),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Invalid number of amplifiers '{}'.", s))
+    }
 }
@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::fs::{self, File};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::panic;
+use std::str::FromStr;
+
+use num::{BigInt, Integer, Signed, CheckedAdd, CheckedMul, FromPrimitive, ToPrimitive};
+
+use itertools::Itertools;
+
+use crate::util;
+use crate::intcode::{Computer, ComputerInput, Limits, RunStatus, analysis, ascii, assembler, disassembler, fuzz, profiler, translator};
+use crate::intcode::debugger::Debugger;
+use crate::intcode::iolog::IoLog;
+use crate::options::{IntcodeCommand, Width};
+
+pub fn run(cmd: &IntcodeCommand) -> Result<(), Box<dyn Error>> {
+    match cmd {
+        IntcodeCommand::Assemble { filename, output } => assemble(filename, output),
+        IntcodeCommand::Disassemble { filename } => disassemble(filename),
+        IntcodeCommand::Graph { filename, output } => graph(filename, output),
+        IntcodeCommand::Translate { filename, name, output } => translate(filename, name, output),
+        IntcodeCommand::Run { filename, ascii, input, input_file, set, width, checked } => match width {
+            Width::W32 => run_program::<i32>(filename, *ascii, input, input_file, set, *checked),
+            Width::W64 => run_program::<i64>(filename, *ascii, input, input_file, set, *checked),
+            Width::W128 => run_program::<i128>(filename, *ascii, input, input_file, set, *checked),
+            Width::Big => run_program::<BigInt>(filename, *ascii, input, input_file, set, *checked),
+        },
+        IntcodeCommand::Debug { filename } => debug(filename),
+        IntcodeCommand::Replay { filename, log, machine } => replay(filename, log, machine),
+        IntcodeCommand::Profile { filename, input, top, disassemble, max_steps } => profile(filename, input, *top, *disassemble, *max_steps),
+        IntcodeCommand::Fuzz { iterations, seed, max_steps } => run_fuzz(*iterations, *seed, *max_steps),
+    }
+}
+
+fn assemble(filename: &PathBuf, output: &Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(filename)?;
+    let program: Vec<i64> = assembler::assemble(&source)?;
+    let text = program.iter().join(",");
+
+    match output {
+        Some(path) => fs::write(path, text + "\n")?,
+        None => println!("{}", text),
+    };
+
+    Ok(())
+}
+
+fn disassemble(filename: &PathBuf) -> Result<(), Box<dyn Error>> {
+    let program = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
+    print!("{}", disassembler::listing(&program));
+
+    Ok(())
+}
+
+fn graph(filename: &PathBuf, output: &Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let program = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
+    let dot = analysis::ControlFlowGraph::new(&program).to_dot();
+
+    match output {
+        Some(path) => fs::write(path, dot)?,
+        None => print!("{}", dot),
+    };
+
+    Ok(())
+}
+
+fn translate(filename: &PathBuf, name: &str, output: &Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let program = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
+    let source = translator::translate(&program, name)?;
+
+    match output {
+        Some(path) => fs::write(path, source)?,
+        None => print!("{}", source),
+    };
+
+    Ok(())
+}
+
+fn run_program<T>(filename: &PathBuf, ascii: bool, input: &[i128], input_file: &Option<PathBuf>, set: &[(i128, i128)], checked: bool) -> Result<(), Box<dyn Error>>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + FromStr + fmt::Display + fmt::Debug + Send + 'static,
+{
+    let program = util::read_comma_separated_integers::<File, T>(File::open(filename)?)?;
+    let mut computer = Computer::new(program, None, None);
+    computer.set_overflow_checks(checked);
+    for &(address, value) in set {
+        computer.write(narrow(address)?, narrow(value)?)?;
+    }
+
+    let stdin = io::stdin();
+    let mut source: Box<dyn BufRead> = match input_file {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(stdin.lock())
+    };
+
+    if ascii {
+        for answer in ascii::run_interactive(&mut computer, source, io::stdout())? {
+            println!("{}", answer);
+        }
+        return Ok(());
+    }
+
+    let interactive = input.is_empty() && input_file.is_none();
+    if input_file.is_some() {
+        let mut text = String::new();
+        source.read_to_string(&mut text)?;
+        push_values(&mut computer, &text)?;
+    }
+    for &value in input {
+        computer.push_input(narrow(value)?)?;
+    }
+
+    let mut lines = source.lines();
+    loop {
+        match computer.resume()? {
+            RunStatus::ProducedOutput(value) => println!("{}", value),
+            RunStatus::NeedsInput if interactive => {
+                let line = lines.next().ok_or("Input ended while the program was waiting for more.")??;
+                push_values(&mut computer, &line)?;
+            },
+            RunStatus::NeedsInput => return Err(Box::from("The program needs more input than was given.")),
+            RunStatus::Halted => break
+        }
+    }
+    println!("Memory[0]: {}", computer.read(T::zero())?);
+
+    Ok(())
+}
+
+/// Gives a computer the values in some text, separated by commas or whitespace.
+fn push_values<T>(computer: &mut Computer<T>, text: &str) -> Result<(), String>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul + FromPrimitive + ToPrimitive + FromStr + fmt::Display + Send + 'static,
+{
+    for value in text.split(|c: char| c == ',' || c.is_whitespace()).filter(|v| !v.is_empty()) {
+        computer.push_input(value.parse().map_err(|_| format!("Invalid input value '{}'.", value))?)?;
+    }
+    Ok(())
+}
+
+/// Converts a value from the command line to the width the program is being run with.
+fn narrow<T: FromPrimitive>(value: i128) -> Result<T, String> {
+    T::from_i128(value).ok_or_else(|| format!("{} is too large for the chosen width.", value))
+}
+
+fn debug(filename: &PathBuf) -> Result<(), Box<dyn Error>> {
+    let program = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
+    let mut debugger = Debugger::new(Computer::new(program, None, None));
+    let stdin = io::stdin();
+    debugger.run(stdin.lock(), io::stdout())?;
+
+    Ok(())
+}
+
+fn profile(filename: &PathBuf, input: &[i64], top: usize, annotate: bool, max_steps: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let program = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
+    let input = ComputerInput::Queue(input.iter().cloned().collect::<VecDeque<i64>>());
+    let mut computer = Computer::new(program.clone(), Some(input), None);
+    computer.set_limits(Limits { max_steps, ..Limits::default() });
+    let annotation = if annotate { Some(program.as_slice()) } else { None };
+
+    match profiler::profile(&mut computer) {
+        Ok(profile) => {
+            println!("{}", profile.report(annotation, top));
+            Ok(())
+        },
+        Err((error, profile)) => {
+            println!("{}", profile.report(annotation, top));
+            Err(Box::new(error))
+        }
+    }
+}
+
+fn replay(filename: &PathBuf, log: &PathBuf, machine: &Option<String>) -> Result<(), Box<dyn Error>> {
+    let program = util::read_comma_separated_integers::<File, i64>(File::open(filename)?)?;
+    let log = IoLog::<i64>::load(log)?;
+    let machines = match machine {
+        Some(m) => vec![m.as_str()],
+        None => log.machines()
+    };
+
+    let mut failures = 0;
+    for machine in &machines {
+        match log.replay(machine, program.clone()) {
+            Ok(computer) => println!("{}: ok ({} inputs, {} outputs, {} steps)",
+                                     machine, log.inputs(machine).len(), log.outputs(machine).len(), computer.steps()),
+            Err(e) => {
+                println!("{}: {}", machine, e);
+                failures += 1;
+            }
+        }
+    }
+
+    match failures {
+        0 => Ok(()),
+        n => Err(format!("{} of {} machines did not replay.", n, machines.len()).into())
+    }
+}
+
+fn run_fuzz(iterations: usize, seed: u64, max_steps: u64) -> Result<(), Box<dyn Error>> {
+    // Panics are caught and reported as failures, so the default message would only repeat them.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let failures = fuzz::fuzz(seed, iterations, max_steps);
+    panic::set_hook(hook);
+
+    for failure in &failures {
+        println!("{}\n", failure);
+    }
+    match failures.len() {
+        0 => {
+            println!("All {} programs passed.", iterations);
+            Ok(())
+        },
+        n => Err(format!("{} of {} programs failed.", n, iterations).into())
+    }
+}